@@ -7,8 +7,19 @@ pub const MEMORY_END: usize = 0x8800_0000;
 pub const PAGE_SIZE: usize = 0x1000;
 pub const PAGE_SIZE_BITS: usize = 0xc;
 pub const MAX_SYSCALL_NUM: usize = 500;
+/// default number of frames a process may map, `sys_setrlimit` changes it
+pub const USER_FRAME_LIMIT: usize = 0x1000;
 
+/// end of the low half of SV39, user addresses above it alias the pages of
+/// TrapContext and the trampoline in the page table
+pub const USER_SPACE_END: usize = 1 << 38;
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
 pub const CLOCK_FREQ: usize = 1250_0000;
-pub const BIG_STRIDE: usize = 131072;
\ No newline at end of file
+pub const BIG_STRIDE: usize = 131072;
+pub const MMIO: &[(usize, usize)] = &[
+    // qemu virt test device, used to power off
+    (0x0010_0000, 0x1000),
+    // VirtIO block device
+    (0x1000_1000, 0x1000),
+];
\ No newline at end of file
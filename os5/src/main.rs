@@ -56,6 +56,40 @@ fn clear_bss() {
     }
 }
 
+/// Boot-time self-tests, only built and run with `--features selftest`.
+#[cfg(feature = "selftest")]
+fn selftest() {
+    mm::frame_limit_test();
+    mm::vpn_range_test();
+    mm::copy_user_test();
+    mm::munmap_adjacent_test();
+    mm::mmap_round_test();
+    mm::page_fault_test();
+    mm::mmap_flags_test();
+    mm::page_table_recycle_test();
+    mm::madvise_test();
+    mm::mmap_quiet_test();
+    mm::share_readonly_test();
+    mm::load_overlap_test();
+    mm::memory_map_test();
+    mm::stack_guard_test();
+    mm::copy_data_at_test();
+    mm::copy_into_page_test();
+    mm::frame_uninit_test();
+    mm::check_range_test();
+    mm::growsdown_test();
+    mm::area_name_test();
+    mm::frame_double_free_test();
+    mm::stack_size_test();
+    mm::munmap_trim_test();
+    mm::translate_perm_test();
+    mm::mm_invariants_test();
+    mm::writeback_test();
+    mm::iter_mappings_test();
+    mm::load_malformed_test();
+    task::wait_exit_test();
+}
+
 #[no_mangle]
 /// the rust entry-point of os
 pub fn rust_main() -> ! {
@@ -64,9 +98,13 @@ pub fn rust_main() -> ! {
     println!("[kernel] Hello, world!");
     mm::init();
     mm::remap_test();
+    #[cfg(feature = "selftest")]
+    selftest();
     task::add_initproc();
     info!("after initproc!");
     trap::init();
+    #[cfg(feature = "selftest")]
+    task::kernel_stack_guard_test();
     trap::enable_timer_interrupt();
     timer::set_next_trigger();
     loader::list_apps();
@@ -167,13 +167,29 @@ impl PhysPageNum {
     }
 }
 
+/// Copy `src` to `offset` bytes into the page of `ppn`, an `src` running past
+/// the end of the page is an error rather than a panic.
+pub fn copy_into_page(ppn: PhysPageNum, offset: usize, src: &[u8]) -> Result<(), isize> {
+    match offset.checked_add(src.len()) {
+        Some(end) if end <= PAGE_SIZE => {
+            ppn.get_bytes_array()[offset..end].copy_from_slice(src);
+            Ok(())
+        }
+        _ => Err(-1),
+    }
+}
+
 pub trait StepByOne {
     fn step(&mut self);
+    fn step_back(&mut self);
 }
 impl StepByOne for VirtPageNum {
     fn step(&mut self) {
         self.0 += 1;
     }
+    fn step_back(&mut self) {
+        self.0 -= 1;
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -241,6 +257,41 @@ where
         }
     }
 }
+impl<T> DoubleEndedIterator for SimpleRangeIterator<T>
+where
+    T: StepByOne + Copy + PartialEq + PartialOrd + Debug,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.current == self.end {
+            None
+        } else {
+            self.end.step_back();
+            Some(self.end)
+        }
+    }
+}
 
 /// a simple range structure for virtual page number
 pub type VPNRange = SimpleRange<VirtPageNum>;
+
+#[allow(unused)]
+/// a simple test for iterating [`VPNRange`] in both directions
+pub fn vpn_range_test() {
+    use alloc::vec::Vec;
+    let range = VPNRange::new(VirtPageNum(0x10), VirtPageNum(0x15));
+    let forward: Vec<VirtPageNum> = range.into_iter().collect();
+    let mut backward: Vec<VirtPageNum> = range.into_iter().rev().collect();
+    assert_eq!(forward.len(), 5);
+    assert_eq!(forward[0], VirtPageNum(0x10));
+    assert_eq!(backward[0], VirtPageNum(0x14));
+    backward.reverse();
+    assert_eq!(forward, backward);
+    // both ends meet in the middle without yielding a page twice
+    let mut iter = range.into_iter();
+    assert_eq!(iter.next(), Some(VirtPageNum(0x10)));
+    assert_eq!(iter.next_back(), Some(VirtPageNum(0x14)));
+    assert_eq!(iter.count(), 3);
+    let empty = VPNRange::new(VirtPageNum(0x10), VirtPageNum(0x10));
+    assert_eq!(empty.into_iter().rev().next(), None);
+    info!("vpn_range_test passed!");
+}
@@ -6,6 +6,7 @@ use crate::config::MEMORY_END;
 use crate::sync::UPSafeCell;
 use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use lazy_static::*;
 
 /// manage a frame which has the same lifecycle as the tracker
@@ -22,6 +23,11 @@ impl FrameTracker {
         }
         Self { ppn }
     }
+    /// Track `ppn` without cleaning it, its old content is left in place.
+    pub fn new_uninit(ppn: PhysPageNum) -> Self {
+        UNINIT_FRAMES.fetch_add(1, Ordering::Relaxed);
+        Self { ppn }
+    }
 }
 
 impl Debug for FrameTracker {
@@ -55,6 +61,22 @@ impl StackFrameAllocator {
         self.end = r.0;
         info!("last {} Physical Frames.", self.end - self.current);
     }
+    /// number of frames that can still be allocated
+    pub fn free_count(&self) -> usize {
+        self.end - self.current + self.recycled.len()
+    }
+    /// Recycle `ppn`, a frame never allocated or already freed is rejected
+    /// before it can end up in the free list twice.
+    pub fn try_dealloc(&mut self, ppn: PhysPageNum) -> Result<(), isize> {
+        let ppn = ppn.0;
+        // validity check
+        if ppn >= self.current || self.recycled.iter().any(|v| *v == ppn) {
+            return Err(-1);
+        }
+        // recycle
+        self.recycled.push(ppn);
+        Ok(())
+    }
 }
 impl FrameAllocator for StackFrameAllocator {
     fn new() -> Self {
@@ -75,13 +97,9 @@ impl FrameAllocator for StackFrameAllocator {
         }
     }
     fn dealloc(&mut self, ppn: PhysPageNum) {
-        let ppn = ppn.0;
-        // validity check
-        if ppn >= self.current || self.recycled.iter().any(|v| *v == ppn) {
-            panic!("Frame ppn={:#x} has not been allocated!", ppn);
+        if self.try_dealloc(ppn).is_err() {
+            panic!("Frame ppn={:#x} has not been allocated!", ppn.0);
         }
-        // recycle
-        self.recycled.push(ppn);
     }
 }
 
@@ -111,6 +129,26 @@ pub fn frame_alloc() -> Option<FrameTracker> {
         .map(FrameTracker::new)
 }
 
+/// allocate a frame without cleaning it, only for frames about to be overwritten in full
+pub fn frame_alloc_uninit() -> Option<FrameTracker> {
+    FRAME_ALLOCATOR
+        .exclusive_access()
+        .alloc()
+        .map(FrameTracker::new_uninit)
+}
+
+/// number of frames handed out by [`frame_alloc_uninit`] so far
+static UNINIT_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+pub fn frame_uninit_count() -> usize {
+    UNINIT_FRAMES.load(Ordering::Relaxed)
+}
+
+/// number of free frames left in the frame allocator
+pub fn frame_free_count() -> usize {
+    FRAME_ALLOCATOR.exclusive_access().free_count()
+}
+
 /// deallocate a frame
 fn frame_dealloc(ppn: PhysPageNum) {
     FRAME_ALLOCATOR.exclusive_access().dealloc(ppn);
@@ -134,3 +172,18 @@ pub fn frame_allocator_test() {
     drop(v);
     info!("frame_allocator_test passed!");
 }
+
+#[allow(unused)]
+/// a double free must be refused instead of recycling the frame twice
+pub fn frame_double_free_test() {
+    // a private allocator over made-up frames, none of them is ever touched
+    let mut allocator = StackFrameAllocator::new();
+    allocator.init(PhysPageNum(0x1000), PhysPageNum(0x1004));
+    let ppn = allocator.alloc().unwrap();
+    assert_eq!(allocator.try_dealloc(ppn), Ok(()));
+    assert_eq!(allocator.try_dealloc(ppn), Err(-1));
+    assert_eq!(allocator.free_count(), 4);
+    // never allocated at all
+    assert_eq!(allocator.try_dealloc(PhysPageNum(0x1003)), Err(-1));
+    info!("frame_double_free_test passed!");
+}
@@ -1,16 +1,23 @@
 //! Implementation of [`MapArea`] and [`MemorySet`].
 
-use super::{frame_alloc, FrameTracker};
+use super::{copy_into_page, StepByOne, VPNRange};
+use super::{frame_alloc, frame_alloc_uninit, frame_free_count, frame_uninit_count, FrameTracker};
 use super::{PTEFlags, PageTable, PageTableEntry};
 use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
-use super::{StepByOne, VPNRange};
-use crate::config::{MEMORY_END, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_STACK_SIZE};
+use crate::config::{
+    MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_FRAME_LIMIT, USER_SPACE_END,
+    USER_STACK_SIZE,
+};
+use crate::loader::get_app_data_by_name;
 use crate::sync::UPSafeCell;
 use crate::task::current_task;
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use lazy_static::*;
+use log::{Level, LevelFilter};
 use riscv::register::satp;
 
 extern "C" {
@@ -36,6 +43,10 @@ lazy_static! {
 pub struct MemorySet {
     page_table: PageTable,
     areas: Vec<MapArea>,
+    /// number of data frames currently mapped by `areas`
+    frame_count: usize,
+    /// max number of data frames `mmap` may bring the space up to
+    frame_limit: usize,
 }
 
 impl MemorySet {
@@ -43,11 +54,22 @@ impl MemorySet {
         Self {
             page_table: PageTable::new(),
             areas: Vec::new(),
+            frame_count: 0,
+            frame_limit: USER_FRAME_LIMIT,
         }
     }
     pub fn token(&self) -> usize {
         self.page_table.token()
     }
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+    pub fn frame_limit(&self) -> usize {
+        self.frame_limit
+    }
+    pub fn set_frame_limit(&mut self, limit: usize) {
+        self.frame_limit = limit;
+    }
     /// Assume that no conflicts.
     pub fn insert_framed_area(
         &mut self,
@@ -60,6 +82,16 @@ impl MemorySet {
             None,
         );
     }
+    /// Frames of a lazy area are only allocated by `handle_page_fault`.
+    pub fn insert_lazy_area(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        permission: MapPermission,
+    ) {
+        self.areas
+            .push(MapArea::new(start_va, end_va, MapType::Framed, permission));
+    }
     pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
         if let Some((idx, area)) = self
             .areas
@@ -67,16 +99,29 @@ impl MemorySet {
             .enumerate()
             .find(|(_, area)| area.vpn_range.get_start() == start_vpn)
         {
+            self.frame_count -= area.data_frames.len();
             area.unmap(&mut self.page_table);
             self.areas.remove(idx);
         }
     }
-    fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
-        map_area.map(&mut self.page_table);
+    fn push(&mut self, map_area: MapArea, data: Option<&[u8]>) {
+        self.try_push(map_area, data).unwrap();
+    }
+    /// Like `push`, but `data` not fitting in the area is an error and
+    /// leaves the space untouched.
+    fn try_push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) -> Result<(), isize> {
         if let Some(data) = data {
-            map_area.copy_data(&mut self.page_table, data);
+            map_area.map_for_data(&mut self.page_table, data.len());
+            if let Err(err) = map_area.copy_data(&mut self.page_table, data) {
+                map_area.unmap(&mut self.page_table);
+                return Err(err);
+            }
+        } else {
+            map_area.map(&mut self.page_table);
         }
+        self.frame_count += map_area.data_frames.len();
         self.areas.push(map_area);
+        Ok(())
     }
     /// Mention that trampoline is not collected by areas.
     fn map_trampoline(&mut self) {
@@ -89,6 +134,8 @@ impl MemorySet {
     /// Without kernel stacks.
     pub fn new_kernel() -> Self {
         let mut memory_set = Self::new_bare();
+        // kernel stacks are the only framed areas here, never limit them
+        memory_set.set_frame_limit(usize::MAX);
         // map trampoline
         memory_set.map_trampoline();
         // map kernel sections
@@ -149,26 +196,75 @@ impl MemorySet {
             ),
             None,
         );
+        info!("mapping memory-mapped registers");
+        for &(base, len) in MMIO {
+            memory_set.push(
+                MapArea::new(
+                    base.into(),
+                    (base + len).into(),
+                    MapType::Identical,
+                    MapPermission::R | MapPermission::W,
+                ),
+                None,
+            );
+        }
         memory_set
     }
     /// Include sections in elf and trampoline and TrapContext and user stack,
     /// also returns user_sp and entry point.
-    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
+    ///
+    /// Fails if a LOAD segment or the user stack would reach into the
+    /// TrapContext/trampoline pages at the top of the address space, or if
+    /// the elf is truncated or malformed, see [`LoadError`].
+    pub fn from_elf(elf_data: &[u8]) -> Result<(Self, usize, usize), LoadError> {
+        Self::from_elf_with_stack(elf_data, USER_STACK_SIZE)
+    }
+    /// Same as `from_elf`, with a user stack of `stack_size` bytes rounded up to pages.
+    pub fn from_elf_with_stack(
+        elf_data: &[u8],
+        stack_size: usize,
+    ) -> Result<(Self, usize, usize), LoadError> {
+        if stack_size == 0 || stack_size / PAGE_SIZE >= USER_FRAME_LIMIT {
+            return Err(LoadError::BadStackSize);
+        }
+        let stack_size = (stack_size + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+        if elf_data.get(..4) != Some(&[0x7f, 0x45, 0x4c, 0x46][..]) {
+            return Err(LoadError::BadMagic);
+        }
+        let elf = xmas_elf::ElfFile::new(elf_data).map_err(|_| LoadError::BadHeader)?;
+        if elf.header.pt1.class() != xmas_elf::header::Class::SixtyFour {
+            return Err(LoadError::BadHeader);
+        }
+        // xmas_elf slices the input unchecked, the program header table must fit
+        let ph_count = elf.header.pt2.ph_count();
+        let ph_entry_size = elf.header.pt2.ph_entry_size() as u64;
+        if ph_entry_size != core::mem::size_of::<xmas_elf::program::ProgramHeader64>() as u64
+            || (ph_count as u64 * ph_entry_size)
+                .checked_add(elf.header.pt2.ph_offset())
+                .map_or(true, |end| end > elf_data.len() as u64)
+        {
+            return Err(LoadError::BadProgramHeader);
+        }
         let mut memory_set = Self::new_bare();
         // map trampoline
         memory_set.map_trampoline();
         // map program headers of elf, with U flag
-        let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
-        let elf_header = elf.header;
-        let magic = elf_header.pt1.magic;
-        assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
-        let ph_count = elf_header.pt2.ph_count();
         let mut max_end_vpn = VirtPageNum(0);
         for i in 0..ph_count {
-            let ph = elf.program_header(i).unwrap();
-            if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
+            let ph = elf
+                .program_header(i)
+                .map_err(|_| LoadError::BadProgramHeader)?;
+            let ph_type = ph.get_type().map_err(|_| LoadError::BadProgramHeader)?;
+            if ph_type == xmas_elf::program::Type::Load {
+                let end = ph
+                    .virtual_addr()
+                    .checked_add(ph.mem_size())
+                    .ok_or(LoadError::OverlapsTrapContext)? as usize;
+                if end > USER_SPACE_END {
+                    return Err(LoadError::OverlapsTrapContext);
+                }
                 let start_va: VirtAddr = (ph.virtual_addr() as usize).into();
-                let end_va: VirtAddr = ((ph.virtual_addr() + ph.mem_size()) as usize).into();
+                let end_va: VirtAddr = end.into();
                 let mut map_perm = MapPermission::U;
                 let ph_flags = ph.flags();
                 if ph_flags.is_read() {
@@ -180,12 +276,24 @@ impl MemorySet {
                 if ph_flags.is_execute() {
                     map_perm |= MapPermission::X;
                 }
-                let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
-                max_end_vpn = map_area.vpn_range.get_end();
-                memory_set.push(
-                    map_area,
-                    Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]),
-                );
+                let map_area =
+                    MapArea::new(start_va, end_va, MapType::Framed, map_perm).with_name("elf");
+                let (start_vpn, end_vpn) =
+                    (map_area.vpn_range.get_start(), map_area.vpn_range.get_end());
+                if memory_set.overlaps(start_vpn, end_vpn) {
+                    return Err(LoadError::OverlappingSegments);
+                }
+                // segments may come in any order, the stack goes above all of them
+                max_end_vpn = max_end_vpn.max(end_vpn);
+                let data = ph
+                    .offset()
+                    .checked_add(ph.file_size())
+                    .filter(|&end| end <= elf_data.len() as u64)
+                    .map(|end| &elf_data[ph.offset() as usize..end as usize])
+                    .ok_or(LoadError::BadProgramHeader)?;
+                memory_set
+                    .try_push(map_area, Some(data))
+                    .map_err(|_| LoadError::DataOverflow)?;
             }
         }
         // map user stack with U flags
@@ -193,16 +301,19 @@ impl MemorySet {
         let mut user_stack_bottom: usize = max_end_va.into();
         // guard page
         user_stack_bottom += PAGE_SIZE;
-        let user_stack_top = user_stack_bottom + USER_STACK_SIZE;
-        memory_set.push(
-            MapArea::new(
-                user_stack_bottom.into(),
-                user_stack_top.into(),
-                MapType::Framed,
-                MapPermission::R | MapPermission::W | MapPermission::U,
-            ),
-            None,
-        );
+        let user_stack_top = user_stack_bottom + stack_size;
+        if user_stack_top > USER_SPACE_END {
+            return Err(LoadError::OverlapsTrapContext);
+        }
+        let mut user_stack = MapArea::new(
+            user_stack_bottom.into(),
+            user_stack_top.into(),
+            MapType::Framed,
+            MapPermission::R | MapPermission::W | MapPermission::U,
+        )
+        .with_name("ustack");
+        user_stack.stack_guard = Some(max_end_vpn);
+        memory_set.push(user_stack, None);
         // map TrapContext
         memory_set.push(
             MapArea::new(
@@ -210,27 +321,48 @@ impl MemorySet {
                 TRAMPOLINE.into(),
                 MapType::Framed,
                 MapPermission::R | MapPermission::W,
-            ),
+            )
+            .with_name("trapctx"),
             None,
         );
-        (
+        if cfg!(debug_assertions) {
+            if let Err(err) = user_remap_test(&memory_set) {
+                warn!("[load] user_remap_test failed: {}", err);
+            }
+        }
+        Ok((
             memory_set,
             user_stack_top,
             elf.header.pt2.entry_point() as usize,
-        )
+        ))
     }
-    /// Copy an identical user_space
+    /// Copy an identical user_space, frames of read-only areas are shared
+    /// instead of copied since neither side can modify them, and so are those
+    /// of `MAP_SHARED` areas which both sides should see writes to.
     pub fn from_existed_user(user_space: &MemorySet) -> MemorySet {
         let mut memory_set = Self::new_bare();
+        memory_set.set_frame_limit(user_space.frame_limit);
         // map trampoline
         memory_set.map_trampoline();
         // copy data sections/trap_context/user_stack
         for area in user_space.areas.iter() {
-            let new_area = MapArea::from_another(area);
+            let mut new_area = MapArea::from_another(area);
+            if area.map_type == MapType::Framed
+                && (area.shared || !area.map_perm.contains(MapPermission::W))
+            {
+                new_area.map_shared(&mut memory_set.page_table, area);
+                memory_set.frame_count += new_area.data_frames.len();
+                memory_set.areas.push(new_area);
+                continue;
+            }
             memory_set.push(new_area, None);
             // copy data from another space
             for vpn in area.vpn_range {
-                let src_ppn = user_space.translate(vpn).unwrap().ppn();
+                // lazy pages not touched yet stay zeroed in the child
+                let src_ppn = match user_space.translate(vpn) {
+                    Some(pte) if pte.is_valid() => pte.ppn(),
+                    _ => continue,
+                };
                 let dst_ppn = memory_set.translate(vpn).unwrap().ppn();
                 dst_ppn
                     .get_bytes_array()
@@ -239,6 +371,9 @@ impl MemorySet {
         }
         memory_set
     }
+    /// Only used by `mm::init` to turn on paging. Traps switch `satp` and
+    /// flush the TLB themselves in `__alltraps` and `__restore`, so this is
+    /// not on the syscall path.
     pub fn activate(&self) {
         let satp = self.page_table.token();
         unsafe {
@@ -249,167 +384,560 @@ impl MemorySet {
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.page_table.translate(vpn)
     }
+    /// Frame and `R W X U` permission of a present page, the other pte flags
+    /// are dropped.
+    pub fn translate_perm(&self, vpn: VirtPageNum) -> Option<(PhysPageNum, MapPermission)> {
+        self.translate(vpn).filter(|pte| pte.is_valid()).map(|pte| {
+            (
+                pte.ppn(),
+                MapPermission::from_bits_truncate(pte.flags().bits()),
+            )
+        })
+    }
+    /// Every page mapped by an area, in the order of `areas`, with its frame and
+    /// permission. The trampoline is not an area and lazy pages not touched yet
+    /// are not mapped, neither is yielded.
+    pub fn iter_mappings(
+        &self,
+    ) -> impl Iterator<Item = (VirtPageNum, PhysPageNum, MapPermission)> + '_ {
+        self.areas.iter().flat_map(move |area| {
+            area.vpn_range
+                .into_iter()
+                .filter_map(move |vpn| self.translate_perm(vpn).map(|(ppn, perm)| (vpn, ppn, perm)))
+        })
+    }
+    /// Split `[va, va + len)` at page boundaries into slices of the frames
+    /// backing it, fails if any page of the range is not mapped.
+    fn user_byte_buffer(&self, va: usize, len: usize) -> Result<Vec<&'static mut [u8]>, isize> {
+        let mut start = va;
+        let end = va.checked_add(len).ok_or(-1isize)?;
+        let mut v = Vec::new();
+        while start < end {
+            let start_va = VirtAddr::from(start);
+            let mut vpn = start_va.floor();
+            let ppn = match self.translate(vpn) {
+                Some(pte) if pte.is_valid() => pte.ppn(),
+                _ => return Err(-1),
+            };
+            vpn.step();
+            let mut end_va: VirtAddr = vpn.into();
+            end_va = end_va.min(VirtAddr::from(end));
+            if end_va.page_offset() == 0 {
+                v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..]);
+            } else {
+                v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..end_va.page_offset()]);
+            }
+            start = end_va.into();
+        }
+        Ok(v)
+    }
+    /// Copy `src` to `dst_va` in this space, nothing is written unless the
+    /// whole destination range is mapped writable for the user.
+    pub fn copy_to_user(&self, dst_va: usize, src: &[u8]) -> Result<(), isize> {
+        if !self.check_range(dst_va, src.len(), MapPermission::W | MapPermission::U) {
+            return Err(-1);
+        }
+        let mut copied = 0;
+        for buffer in self.user_byte_buffer(dst_va, src.len())? {
+            buffer.copy_from_slice(&src[copied..copied + buffer.len()]);
+            copied += buffer.len();
+        }
+        Ok(())
+    }
+    /// Read `len` bytes starting from `src_va` in this space, which must be
+    /// readable for the user.
+    pub fn copy_from_user(&self, src_va: usize, len: usize) -> Result<Vec<u8>, isize> {
+        if !self.check_range(src_va, len, MapPermission::R | MapPermission::U) {
+            return Err(-1);
+        }
+        let mut data = Vec::with_capacity(len);
+        for buffer in self.user_byte_buffer(src_va, len)? {
+            data.extend_from_slice(buffer);
+        }
+        Ok(data)
+    }
+    /// Whether every page of `[va, va + len)` is present with all of `required`,
+    /// a user pointer should pass this before the kernel touches it.
+    pub fn check_range(&self, va: usize, len: usize, required: MapPermission) -> bool {
+        if len == 0 {
+            return true;
+        }
+        let end = match va.checked_add(len) {
+            Some(end) => end,
+            None => return false,
+        };
+        VPNRange::new(VirtAddr::from(va).floor(), VirtAddr::from(end).ceil())
+            .into_iter()
+            .all(|vpn| {
+                self.translate_perm(vpn)
+                    .map_or(false, |(_, perm)| perm.contains(required))
+            })
+    }
+    /// Write a [`MapRecord`] for each area, at most `len` of them, to `buf`
+    /// in this space and return how many were written.
+    pub fn memory_map(&self, buf: usize, len: usize) -> isize {
+        let records: Vec<MapRecord> = self
+            .areas
+            .iter()
+            .take(len)
+            .map(|area| {
+                let mut name = [0u8; 8];
+                let tag = area.name.unwrap_or("").as_bytes();
+                let n = tag.len().min(name.len() - 1);
+                name[..n].copy_from_slice(&tag[..n]);
+                MapRecord {
+                    start_va: VirtAddr::from(area.vpn_range.get_start()).into(),
+                    end_va: VirtAddr::from(area.vpn_range.get_end()).into(),
+                    perm: area.map_perm.bits() as usize,
+                    map_type: area.map_type as usize,
+                    name,
+                }
+            })
+            .collect();
+        let bytes = unsafe {
+            core::slice::from_raw_parts(
+                records.as_ptr() as *const u8,
+                records.len() * core::mem::size_of::<MapRecord>(),
+            )
+        };
+        match self.copy_to_user(buf, bytes) {
+            Ok(()) => records.len() as isize,
+            Err(err) => err,
+        }
+    }
+    /// Try to resolve a page fault from user space at `va`, a resolved fault
+    /// should re-execute the faulting instruction.
+    pub fn handle_page_fault(&mut self, va: VirtAddr, cause: FaultCause) -> FaultResult {
+        let vpn = va.floor();
+        let area = match self
+            .areas
+            .iter_mut()
+            .find(|area| area.vpn_range.get_start() <= vpn && vpn < area.vpn_range.get_end())
+        {
+            Some(area) => area,
+            None => return self.grow_down(vpn, cause),
+        };
+        let required = cause.permission() | MapPermission::U;
+        if !area.map_perm.contains(required) {
+            return FaultResult::PermissionDenied;
+        }
+        // the page is present, so its flags forbid the access
+        if area.map_type != MapType::Framed || area.data_frames.contains_key(&vpn) {
+            return FaultResult::PermissionDenied;
+        }
+        if self.frame_count >= self.frame_limit {
+            return FaultResult::LimitExceeded;
+        }
+        area.map_one(&mut self.page_table, vpn);
+        self.frame_count += 1;
+        FaultResult::Resolved
+    }
+    /// Extend a `MAP_GROWSDOWN` area down to the faulting `vpn` if it lies in
+    /// the room reserved below the area and no other area is in between.
+    fn grow_down(&mut self, vpn: VirtPageNum, cause: FaultCause) -> FaultResult {
+        let idx = match self.areas.iter().position(|area| {
+            area.grow_limit.map_or(false, |limit| {
+                limit <= vpn && vpn < area.vpn_range.get_start()
+            })
+        }) {
+            Some(idx) => idx,
+            None => return FaultResult::Unmapped,
+        };
+        let start = self.areas[idx].vpn_range.get_start();
+        if self.overlaps(vpn, start) {
+            return FaultResult::Unmapped;
+        }
+        let required = cause.permission() | MapPermission::U;
+        if !self.areas[idx].map_perm.contains(required) {
+            return FaultResult::PermissionDenied;
+        }
+        if self.frame_count >= self.frame_limit {
+            return FaultResult::LimitExceeded;
+        }
+        // pages skipped over stay unmapped until they fault themselves
+        let area = &mut self.areas[idx];
+        area.vpn_range = VPNRange::new(vpn, area.vpn_range.get_end());
+        area.len += (start.0 - vpn.0) * PAGE_SIZE;
+        area.map_one(&mut self.page_table, vpn);
+        self.frame_count += 1;
+        FaultResult::Resolved
+    }
+    /// Free the frames of mapped pages in `[start, start + len)` but keep their
+    /// areas, so the next access faults in a zeroed page.
+    pub fn madvise_dontneed(&mut self, start: usize, len: usize) -> isize {
+        if len == 0 {
+            return 0;
+        }
+        let end = match start.checked_add(len) {
+            Some(end) => end,
+            None => return -1,
+        };
+        let (lvpn, rvpn) = (VirtAddr::from(start).floor(), VirtAddr::from(end).ceil());
+        // only user areas, TrapContext must never lose its frame
+        if VPNRange::new(lvpn, rvpn).into_iter().any(|vpn| {
+            !self.areas.iter().any(|area| {
+                area.map_perm.contains(MapPermission::U)
+                    && area.vpn_range.get_start() <= vpn
+                    && vpn < area.vpn_range.get_end()
+            })
+        }) {
+            return -1;
+        }
+        for area in self.areas.iter_mut() {
+            if area.map_type != MapType::Framed {
+                continue;
+            }
+            let l = area.vpn_range.get_start().max(lvpn);
+            let r = area.vpn_range.get_end().min(rvpn);
+            if l >= r {
+                continue;
+            }
+            for vpn in VPNRange::new(l, r) {
+                if area.data_frames.contains_key(&vpn) {
+                    area.unmap_one(&mut self.page_table, vpn);
+                    self.frame_count -= 1;
+                }
+            }
+        }
+        0
+    }
+    /// Register `writeback` for the area starting at `start_va`, it only ever
+    /// runs if the area is shared and writable.
+    pub fn set_writeback(&mut self, start_va: usize, writeback: Writeback) -> Result<(), isize> {
+        let start_vpn = VirtAddr::from(start_va).floor();
+        let area = self
+            .areas
+            .iter_mut()
+            .find(|area| area.vpn_range.get_start() == start_vpn)
+            .ok_or(-1isize)?;
+        area.writeback = Some(writeback);
+        Ok(())
+    }
+    /// Tear down the whole space, data frames and page-table nodes alike,
+    /// so an exited process gives them back before it is waited for.
     pub fn recycle_data_pages(&mut self) {
-        //*self = Self::new_bare();
+        for area in self.areas.iter() {
+            area.write_back_all(&self.page_table);
+        }
         self.areas.clear();
+        self.page_table.recycle();
+        self.frame_count = 0;
     }
-    pub fn mmap(&mut self, start: usize, end: usize, prot: usize) -> isize {
-        let (lvpn, rvpn) = (VirtAddr::from(start).floor(), VirtAddr::from(end).ceil());
-        let range = VPNRange::new(lvpn, rvpn);
-
-        self.areas.iter().for_each(|area| {
-            info!("l, r, {:?}, {:?}", area.vpn_range.get_start(), area.vpn_range.get_end());
-        });
-        info!(
-            "[map]: lvpn: {:?}, rvpn: {:?}, start: {:#x},end: {:#x}, pt: {:#x}",
+    /// Whether `[lvpn, rvpn)` intersects any area or stack guard page.
+    fn overlaps(&self, lvpn: VirtPageNum, rvpn: VirtPageNum) -> bool {
+        self.areas.iter().any(|area| {
+            (area.vpn_range.get_end() > area.vpn_range.get_start()
+                && lvpn < area.vpn_range.get_end()
+                && rvpn > area.vpn_range.get_start())
+                || area
+                    .stack_guard
+                    .map_or(false, |guard| lvpn <= guard && guard < rvpn)
+        })
+    }
+    /// Find the start of the free range of `pages` pages which is closest to `hint`,
+    /// only ranges right at `hint` or next to an existing area are considered.
+    fn find_free_range(&self, hint: VirtPageNum, pages: usize) -> Option<VirtPageNum> {
+        let limit = VirtAddr::from(TRAP_CONTEXT).floor().0;
+        // the null page is never handed out
+        let mut candidates = vec![hint.0.max(1)];
+        for area in self.areas.iter() {
+            candidates.push(area.vpn_range.get_end().0);
+            if let Some(start) = area.vpn_range.get_start().0.checked_sub(pages) {
+                candidates.push(start);
+            }
+        }
+        candidates
+            .into_iter()
+            .filter(|&start| {
+                start > 0
+                    && start + pages <= limit
+                    && !self.overlaps(VirtPageNum(start), VirtPageNum(start + pages))
+            })
+            .min_by_key(|&start| {
+                if start > hint.0 {
+                    start - hint.0
+                } else {
+                    hint.0 - start
+                }
+            })
+            .map(VirtPageNum)
+    }
+    /// Map `[start, start + len)` with the end rounded up to a page boundary,
+    /// `munmap` rounds the same way so any `len` within the last page works.
+    ///
+    /// Without `MAP_FIXED` in a non-zero `flags`, `start` is only a hint and an
+    /// occupied range is moved to the closest free one, the chosen address is
+    /// returned then. A hint in the null page is moved the same way, so a hint
+    /// never returns 0. Otherwise 0 is returned on success.
+    ///
+    /// With `MAP_GROWSDOWN` only the top page is mapped, the area grows down
+    /// into the rest of the range as pages below it fault.
+    pub fn mmap(&mut self, start: usize, len: usize, prot: usize, flags: usize) -> isize {
+        if len == 0 {
+            return 0;
+        }
+        let end = match start.checked_add(len) {
+            Some(end) => end,
+            None => return -1,
+        };
+        if end > USER_SPACE_END {
+            debug!("[map] [{:#x}, {:#x}) is past the user space", start, end);
+            return -1;
+        }
+        let fixed = flags == 0 || flags & MAP_FIXED != 0;
+        let (mut lvpn, mut rvpn) = (VirtAddr::from(start).floor(), VirtAddr::from(end).ceil());
+        self.trace_areas();
+        trace!(
+            "[map] lvpn: {:?}, rvpn: {:?}, start: {:#x}, end: {:#x}, pt: {:#x}",
             lvpn,
             rvpn,
             start,
             end,
             self.page_table.token()
         );
-        if self
-            .areas
-            .iter()
-            .any(|area| area.vpn_range.get_end() > area.vpn_range.get_start() && lvpn < area.vpn_range.get_end() && rvpn > area.vpn_range.get_start())
-        {
-            // [start, end)
-            println!("already mapped");
-            info!("end,{:?}",self.page_table.translate(rvpn).unwrap().ppn());
-            return -1;
+        if self.overlaps(lvpn, rvpn) || (!fixed && lvpn.0 == 0) {
+            if fixed {
+                debug!("[map] [{:#x}, {:#x}) is already mapped", start, end);
+                return -1;
+            }
+            let pages = rvpn.0 - lvpn.0;
+            match self.find_free_range(lvpn, pages) {
+                Some(vpn) if VirtAddr::from(VirtPageNum(vpn.0 + pages)).0 <= USER_SPACE_END => {
+                    debug!("[map] relocated from {:?} to {:?}", lvpn, vpn);
+                    lvpn = vpn;
+                    rvpn = VirtPageNum(vpn.0 + pages);
+                }
+                _ => return -1,
+            }
+        }
+        let grows_down = flags & MAP_GROWSDOWN != 0;
+        let map_start = if grows_down {
+            VirtPageNum(rvpn.0 - 1)
+        } else {
+            lvpn
+        };
+        if self.frame_count + (rvpn.0 - map_start.0) > self.frame_limit {
+            debug!(
+                "[map] frame limit exceeded: {} mapped, {} requested, limit {}",
+                self.frame_count,
+                rvpn.0 - map_start.0,
+                self.frame_limit
+            );
+            return -ENOMEM;
         }
         let mut permission = MapPermission::from_bits((prot as u8) << 1).unwrap();
         permission.set(MapPermission::U, true);
 
-        self.insert_framed_area(lvpn.into(), rvpn.into(), permission);
-
-        info!("[map] [test] ");
-        range.into_iter().for_each(|vpn| {
-            match self.translate(vpn) {
-                Some(v) => info!("yes {:?}", v.ppn()),
-                None => info!("male"),
-            };
-        });
-        // self.areas.iter().for_each(|area| {
-        //     let (lvpn, rvpn) = (area.get_start(), area.get_end());
-        //     info!(
-        //         "l, r, {:?}, {:?}, {:?}, {:?}",
-        //         area.get_start(),
-        //         area.get_end(),
-        //         self.translate(lvpn).unwrap().ppn(),
-        //         self.translate(rvpn).unwrap().ppn()
-        //     );
-        // });
-        // show_frame_status();
-        0
+        let mut map_area = MapArea::new(map_start.into(), rvpn.into(), MapType::Framed, permission)
+            .with_name("mmap");
+        map_area.shared = flags & MAP_SHARED != 0;
+        if grows_down {
+            map_area.len = PAGE_SIZE;
+            map_area.grow_limit = Some(lvpn);
+        } else {
+            map_area.len = len;
+        }
+        self.push(map_area, None);
+        self.trace_range(lvpn, rvpn);
+        if fixed {
+            0
+        } else {
+            VirtAddr::from(lvpn).0 as isize
+        }
     }
-    pub fn munmap(&mut self, start: usize, end: usize) -> isize {
-        println!("unmap!!!,start: {:#x}, end: {:#x}", start, end);
+    /// Unmap `[start, start + len)` rounded up to pages just like `mmap`.
+    /// A range may cover whole areas and the head or tail of an area, but not
+    /// a hole in the middle of one.
+    /// Returns the number of pages unmapped, or -1 if the range is not fully mapped.
+    pub fn munmap(&mut self, start: usize, len: usize) -> isize {
+        if len == 0 {
+            return 0;
+        }
+        let end = match start.checked_add(len) {
+            Some(end) => end,
+            None => return -1,
+        };
         let (lvpn, rvpn) = (VirtAddr::from(start).floor(), VirtAddr::from(end).ceil());
-        let range = VPNRange::new(lvpn, rvpn);
-        // println!("unmap!!!");
-        if self
-            .areas
-            .iter()
-            .filter_map(|area| {
-                let (start, end) = (area.vpn_range.get_start(), area.vpn_range.get_end());
-                if start >= lvpn && end <= rvpn {
-                    Some(end.0 - start.0)
-                } else {
-                    None
-                }
-            })
-            .sum::<usize>()
-            < (rvpn.0 - lvpn.0)
-        {
-            println!("already mapped");
+        trace!("[unmap] start: {:#x}, end: {:#x}", start, end);
+        let mut covered = 0;
+        for area in self.areas.iter() {
+            let (l, r) = (area.vpn_range.get_start(), area.vpn_range.get_end());
+            if l.max(lvpn) >= r.min(rvpn) {
+                continue;
+            }
+            if !area.map_perm.contains(MapPermission::U) {
+                debug!("[unmap] [{:#x}, {:#x}) is not a user range", start, end);
+                return -1;
+            }
+            // only whole areas, heads and tails can be unmapped
+            if l < lvpn && rvpn < r {
+                debug!("[unmap] [{:#x}, {:#x}) would split an area", start, end);
+                return -1;
+            }
+            covered += r.min(rvpn).0 - l.max(lvpn).0;
+        }
+        if covered < rvpn.0 - lvpn.0 {
+            debug!("[unmap] [{:#x}, {:#x}) is not fully mapped", start, end);
             return -1;
         }
-        // if range
-        //     .into_iter()
-        //     .any(|vpn|
-        //         match self.page_table.translate(vpn) {
-        //             Some(v) => {
-        //                 // println!("?1: {:?}, {:?}", vpn, v.ppn());
-        //                 // if v.ppn().0 == 0x0 {
-        //                 //     true
-        //                 // } else {
-        //                     false
-        //                 // }
-        //             }
-        //             None => true,}
-        //     )
-        // {
-        //     info!("[remove frame] not");
-        //     return -1;
-        // }
-        // info!("unmap!!! real pt: {:#x}", self.page_table.token());
-        // self.areas = self
-        //     .areas
-        //     .to_owned()
-        //     .into_iter()
-        //     .filter_map(|mut area| {
-        //         show_frame_status();
-        //         因为自动drop会导致回收行为，丢失所有权就寄了
-        //         let l = area.get_start();
-        //         let r = area.get_end();
-        //         info!(
-        //             "[unmap] [find]: l: {:?}, r: {:?}, start: {:?}, end: {:?}",
-        //             l, r, start, end
-        //         );
-        //         if l < r && start <= l && r <= end {
-        //             info!("[unmap]: success,l,r:({:?}, {:?})", l, r);
-        //             match self.translate(l) {
-        //                 Some(v) => info!("male {:?}", v.ppn()),
-        //                 None => info!("yes"),
-        //             }
-        //             area.unmap(&mut self.page_table);
-        //             None
-        //         } else {
-        //             Some(area)
-        //         }
-        //     })
-        //     .collect::<Vec<MapArea>>();
-        let pte = &mut self.page_table;
-        self.areas.iter_mut().for_each(|area| {
+        let page_table = &mut self.page_table;
+        let frame_count = &mut self.frame_count;
+        let mut unmapped = 0;
+        // every area inside the range is unmapped and dropped right here,
+        // so none of them can survive with an empty vpn_range
+        self.areas.retain_mut(|area| {
             let l = area.vpn_range.get_start();
             let r = area.vpn_range.get_end();
-            info!(
-                "[unmap] [find]: l: {:?}, r: {:?}, start: {:?}, end: {:?}",
-                l, r, lvpn, rvpn
-            );
+            if l == r {
+                return false;
+            }
             if lvpn <= l && r <= rvpn {
-                info!("[unmap]: success,l,r:({:?}, {:?})", l, r);
-                // match self.translate(l) {
-                //     Some(v) => info!("male {:?}", v.ppn()),
-                //     None => info!("yes"),
-                // }
-                area.unmap(pte);
-                area.vpn_range = VPNRange::new(l, l);
+                trace!(
+                    "[unmap] area {} [{:?}, {:?}), len: {:#x}",
+                    area.name.unwrap_or("-"),
+                    l,
+                    r,
+                    area.len
+                );
+                *frame_count -= area.data_frames.len();
+                unmapped += r.0 - l.0;
+                area.unmap(page_table);
+                false
+            } else if lvpn <= l && l < rvpn {
+                // the head goes, and so does the room to grow down into it
+                *frame_count -= area.unmap_range(page_table, l, rvpn);
+                unmapped += rvpn.0 - l.0;
+                area.vpn_range = VPNRange::new(rvpn, r);
+                area.len = area.len.min((r.0 - rvpn.0) * PAGE_SIZE);
+                area.grow_limit = None;
+                true
+            } else if lvpn < r && r <= rvpn {
+                *frame_count -= area.unmap_range(page_table, lvpn, r);
+                unmapped += r.0 - lvpn.0;
+                area.vpn_range = VPNRange::new(l, lvpn);
+                area.len = area.len.min((lvpn.0 - l.0) * PAGE_SIZE);
+                true
+            } else {
+                true
             }
         });
-        self.areas.retain(|area| area.vpn_range.get_start() < area.vpn_range.get_end());
-        info!("[unmap] [test] ");
-        self.areas.iter().for_each(|area| {
-            info!("l, r, {:?}, {:?}", area.vpn_range.get_start(), area.vpn_range.get_end());
-        });
-        range.into_iter().for_each(|vpn| match self.translate(vpn) {
-            Some(v) => info!("male {:?}, {:?}", vpn, v.ppn()),
-            None => info!("yes"),
-        });
-        0
+        self.trace_areas();
+        self.trace_range(lvpn, rvpn);
+        unmapped as isize
+    }
+    /// Dump all areas, skipped entirely unless logging at trace level.
+    fn trace_areas(&self) {
+        if log_enabled!(Level::Trace) {
+            for area in self.areas.iter() {
+                trace!(
+                    "area {} [{:?}, {:?})",
+                    area.name.unwrap_or("-"),
+                    area.vpn_range.get_start(),
+                    area.vpn_range.get_end()
+                );
+            }
+        }
+    }
+    /// Dump the translation of each page in `[lvpn, rvpn)` at trace level.
+    fn trace_range(&self, lvpn: VirtPageNum, rvpn: VirtPageNum) {
+        if log_enabled!(Level::Trace) {
+            for vpn in VPNRange::new(lvpn, rvpn) {
+                let ppn = self
+                    .translate(vpn)
+                    .filter(|pte| pte.is_valid())
+                    .map(|pte| pte.ppn());
+                trace!("{:?} -> {:?}", vpn, ppn);
+            }
+        }
+    }
+    /// Every area spans at least one page, `-2` otherwise.
+    pub fn check_areas_nonempty(&self) -> Result<(), isize> {
+        if self
+            .areas
+            .iter()
+            .all(|area| area.vpn_range.get_start() < area.vpn_range.get_end())
+        {
+            Ok(())
+        } else {
+            Err(-2)
+        }
+    }
+    /// No page belongs to two areas, `-3` otherwise.
+    pub fn check_areas_disjoint(&self) -> Result<(), isize> {
+        let mut ranges: Vec<(VirtPageNum, VirtPageNum)> = self
+            .areas
+            .iter()
+            .map(|area| (area.vpn_range.get_start(), area.vpn_range.get_end()))
+            .collect();
+        ranges.sort();
+        if ranges.windows(2).all(|w| w[0].1 <= w[1].0) {
+            Ok(())
+        } else {
+            Err(-3)
+        }
+    }
+    /// A page of `[lvpn, rvpn)` is present exactly when an area maps it, and
+    /// to the frame that area holds, `-4` otherwise.
+    pub fn check_translate(&self, lvpn: VirtPageNum, rvpn: VirtPageNum) -> Result<(), isize> {
+        for vpn in VPNRange::new(lvpn, rvpn) {
+            let expected = self
+                .areas
+                .iter()
+                .find(|area| area.vpn_range.get_start() <= vpn && vpn < area.vpn_range.get_end())
+                .and_then(|area| match area.map_type {
+                    MapType::Identical => Some(PhysPageNum(vpn.0)),
+                    MapType::Framed => area.data_frames.get(&vpn).map(|frame| frame.ppn),
+                });
+            if self.translate_perm(vpn).map(|(ppn, _)| ppn) != expected {
+                return Err(-4);
+            }
+        }
+        Ok(())
+    }
+    /// `frame_count` is the number of frames held by the areas, `-5` otherwise.
+    pub fn check_frame_count(&self) -> Result<(), isize> {
+        let frames: usize = self.areas.iter().map(|area| area.data_frames.len()).sum();
+        if frames == self.frame_count {
+            Ok(())
+        } else {
+            Err(-5)
+        }
+    }
+    /// All of the checks above, translations are only checked in `[lvpn, rvpn)`.
+    pub fn check_invariants(&self, lvpn: VirtPageNum, rvpn: VirtPageNum) -> Result<(), isize> {
+        self.check_areas_nonempty()?;
+        self.check_areas_disjoint()?;
+        self.check_translate(lvpn, rvpn)?;
+        self.check_frame_count()
     }
-      
 }
 
 /// map area structure, controls a contiguous piece of virtual memory
 pub struct MapArea {
     vpn_range: VPNRange,
-    data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+    /// frames may be shared with the same area of a forked space
+    data_frames: BTreeMap<VirtPageNum, Arc<FrameTracker>>,
     map_type: MapType,
     map_perm: MapPermission,
+    /// length in bytes as requested, `vpn_range` is this rounded up to pages
+    len: usize,
+    /// lowest page a `MAP_GROWSDOWN` area may grow down to
+    grow_limit: Option<VirtPageNum>,
+    /// guard page right below a user stack, kept unmapped to catch overflows
+    /// and freed for `mmap` once the stack is unmapped
+    stack_guard: Option<VirtPageNum>,
+    /// what the area holds, shown in logs and `memory_map`
+    name: Option<&'static str>,
+    /// mapped with `MAP_SHARED`, writes are meant to be seen by others
+    shared: bool,
+    /// where dirty pages of a shared writable area go before their frames
+    /// are dropped, nothing to write back to for anonymous maps
+    writeback: Option<Writeback>,
 }
 
+/// Called with a dirty page and its content, e.g. to write it to a file.
+pub type Writeback = fn(VirtPageNum, &[u8]);
+
 impl MapArea {
     pub fn new(
         start_va: VirtAddr,
@@ -424,14 +952,30 @@ impl MapArea {
             data_frames: BTreeMap::new(),
             map_type,
             map_perm,
+            len: end_va.0 - start_va.0,
+            grow_limit: None,
+            stack_guard: None,
+            name: None,
+            shared: false,
+            writeback: None,
         }
     }
+    pub fn with_name(mut self, name: &'static str) -> Self {
+        self.name = Some(name);
+        self
+    }
     pub fn from_another(another: &MapArea) -> Self {
         Self {
             vpn_range: VPNRange::new(another.vpn_range.get_start(), another.vpn_range.get_end()),
             data_frames: BTreeMap::new(),
             map_type: another.map_type,
             map_perm: another.map_perm,
+            len: another.len,
+            grow_limit: another.grow_limit,
+            stack_guard: another.stack_guard,
+            name: another.name,
+            shared: another.shared,
+            writeback: another.writeback,
         }
     }
     pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
@@ -443,57 +987,153 @@ impl MapArea {
             MapType::Framed => {
                 let frame = frame_alloc().unwrap();
                 ppn = frame.ppn;
-                self.data_frames.insert(vpn, frame);
+                self.data_frames.insert(vpn, Arc::new(frame));
             }
         }
         let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
         page_table.map(vpn, ppn, pte_flags);
     }
 
+    /// Map the frames of `another` into this area, sharing them.
+    pub fn map_shared(&mut self, page_table: &mut PageTable, another: &MapArea) {
+        assert_eq!(self.map_type, MapType::Framed);
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+        for (vpn, frame) in another.data_frames.iter() {
+            page_table.map(*vpn, frame.ppn, pte_flags);
+            self.data_frames.insert(*vpn, frame.clone());
+        }
+    }
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
         #[allow(clippy::single_match)]
         match self.map_type {
             MapType::Framed => {
-                self.data_frames.remove(&vpn);
+                self.write_back(page_table, vpn);
+                // a lazy page which has never been touched is not in the page table
+                if self.data_frames.remove(&vpn).is_none() {
+                    return;
+                }
             }
             _ => {}
         }
         page_table.unmap(vpn);
     }
+    /// Pass `vpn` to the writeback if it is dirty and the area is shared and
+    /// writable, private areas have no one else to show their writes to.
+    fn write_back(&self, page_table: &PageTable, vpn: VirtPageNum) {
+        let writeback = match self.writeback {
+            Some(writeback) if self.shared && self.map_perm.contains(MapPermission::W) => writeback,
+            _ => return,
+        };
+        match page_table.translate(vpn) {
+            Some(pte) if pte.is_valid() && pte.dirty() => {
+                writeback(vpn, pte.ppn().get_bytes_array());
+            }
+            _ => {}
+        }
+    }
+    fn write_back_all(&self, page_table: &PageTable) {
+        for vpn in self.data_frames.keys() {
+            self.write_back(page_table, *vpn);
+        }
+    }
     pub fn map(&mut self, page_table: &mut PageTable) {
         for vpn in self.vpn_range {
             self.map_one(page_table, vpn);
         }
     }
+    /// Map a framed area whose first `data_len` bytes are about to be copied in,
+    /// pages covered in full skip cleaning their frames.
+    pub fn map_for_data(&mut self, page_table: &mut PageTable, data_len: usize) {
+        assert_eq!(self.map_type, MapType::Framed);
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+        let full_pages = data_len / PAGE_SIZE;
+        for (i, vpn) in self.vpn_range.into_iter().enumerate() {
+            if i >= full_pages {
+                self.map_one(page_table, vpn);
+                continue;
+            }
+            let frame = frame_alloc_uninit().unwrap();
+            page_table.map(vpn, frame.ppn, pte_flags);
+            self.data_frames.insert(vpn, Arc::new(frame));
+        }
+    }
     pub fn unmap(&mut self, page_table: &mut PageTable) {
         for vpn in self.vpn_range {
             self.unmap_one(page_table, vpn);
         }
     }
+    /// Unmap `[lvpn, rvpn)` but leave `vpn_range` to the caller, returns the
+    /// number of frames dropped.
+    pub fn unmap_range(
+        &mut self,
+        page_table: &mut PageTable,
+        lvpn: VirtPageNum,
+        rvpn: VirtPageNum,
+    ) -> usize {
+        let frames = self.data_frames.len();
+        for vpn in VPNRange::new(lvpn, rvpn) {
+            self.unmap_one(page_table, vpn);
+        }
+        frames - self.data_frames.len()
+    }
     /// data: start-aligned but maybe with shorter length
     /// assume that all frames were cleared before
-    pub fn copy_data(&mut self, page_table: &mut PageTable, data: &[u8]) {
+    pub fn copy_data(&mut self, page_table: &mut PageTable, data: &[u8]) -> Result<(), isize> {
+        self.copy_data_at(page_table, 0, data)
+    }
+    /// Copy `data` to `offset` bytes past the start of the area, which may be
+    /// anywhere inside a page. Bytes outside of `[offset, offset + data.len())`
+    /// are left untouched, so pages added by growing the area can be filled alone.
+    ///
+    /// Data running past the last page is an error, with the pages before it copied.
+    pub fn copy_data_at(
+        &mut self,
+        page_table: &mut PageTable,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<(), isize> {
         assert_eq!(self.map_type, MapType::Framed);
-        let mut start: usize = 0;
-        let mut current_vpn = self.vpn_range.get_start();
-        let len = data.len();
-        loop {
-            let src = &data[start..len.min(start + PAGE_SIZE)];
-            let dst = &mut page_table
-                .translate(current_vpn)
-                .unwrap()
-                .ppn()
-                .get_bytes_array()[..src.len()];
-            dst.copy_from_slice(src);
-            start += PAGE_SIZE;
-            if start >= len {
-                break;
+        let start: usize = VirtAddr::from(self.vpn_range.get_start()).into();
+        let mut va = start.checked_add(offset).ok_or(-1)?;
+        let mut rest = data;
+        while !rest.is_empty() {
+            let vpn = VirtAddr::from(va).floor();
+            if vpn >= self.vpn_range.get_end() {
+                return Err(-1);
             }
-            current_vpn.step();
+            let page_offset = VirtAddr::from(va).page_offset();
+            // the last page takes whatever is left, bounds-checked below
+            let n = if vpn.0 + 1 < self.vpn_range.get_end().0 {
+                rest.len().min(PAGE_SIZE - page_offset)
+            } else {
+                rest.len()
+            };
+            let ppn = match page_table.translate(vpn) {
+                Some(pte) if pte.is_valid() => pte.ppn(),
+                _ => return Err(-1),
+            };
+            copy_into_page(ppn, page_offset, &rest[..n])?;
+            rest = &rest[n..];
+            va += n;
         }
+        Ok(())
     }
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+/// an area as reported to user space by [`MemorySet::memory_map`]
+pub struct MapRecord {
+    pub start_va: usize,
+    pub end_va: usize,
+    /// bits of [`MapPermission`]
+    pub perm: usize,
+    /// [`MapType`] as an integer
+    pub map_type: usize,
+    /// tag of the area, NUL padded
+    pub name: [u8; 8],
+}
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 /// map type for memory set: identical or framed
 pub enum MapType {
@@ -501,26 +1141,152 @@ pub enum MapType {
     Framed,
 }
 
-pub fn mmap(start: usize, len: usize, prot: usize) -> isize {
+#[derive(Copy, Clone, PartialEq, Debug)]
+/// kind of access which caused a page fault
+pub enum FaultCause {
+    Load,
+    Store,
+    Instruction,
+}
+
+impl FaultCause {
+    /// permission an area needs to allow this kind of access
+    fn permission(self) -> MapPermission {
+        match self {
+            FaultCause::Load => MapPermission::R,
+            FaultCause::Store => MapPermission::W,
+            FaultCause::Instruction => MapPermission::X,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+/// result of [`MemorySet::handle_page_fault`]
+pub enum FaultResult {
+    /// a frame has been mapped for the page, the access can be retried
+    Resolved,
+    /// no area contains the address
+    Unmapped,
+    /// the area does not allow this kind of access
+    PermissionDenied,
+    /// mapping the page would exceed the frame limit
+    LimitExceeded,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+/// reason for [`MemorySet::from_elf`] to reject an elf
+pub enum LoadError {
+    /// a LOAD segment or the user stack reaches past `USER_SPACE_END`, where
+    /// it would alias TrapContext/trampoline
+    OverlapsTrapContext,
+    /// the file data of a LOAD segment does not fit in its pages
+    DataOverflow,
+    /// the user stack is empty or takes more frames than a process may have
+    BadStackSize,
+    /// the data does not start with the elf magic
+    BadMagic,
+    /// the elf header is truncated or not that of a 64-bit elf
+    BadHeader,
+    /// the program header table or the file data of a segment lies outside
+    /// of the data, or a segment has an unknown type
+    BadProgramHeader,
+    /// two LOAD segments share a page
+    OverlappingSegments,
+}
+
+pub fn mmap(start: usize, len: usize, prot: usize, flags: usize) -> isize {
     if len == 0 {
-        info!("reason1");
         return 0;
     }
+    if flags & !(MAP_SHARED | MAP_PRIVATE | MAP_FIXED | MAP_GROWSDOWN) != 0 {
+        return -1;
+    }
     // 0，1，2位有效，其他位必须为0,mask => b 0...0111 =>0x7
     if (prot >> 3) != 0 || (prot & 0x7) == 0 || start % 4096 != 0 {
-        info!("reason2");
+        debug!("[map] bad prot {:#x} or unaligned start {:#x}", prot, start);
         return -1;
     }
     if let Some(cur_tcb) = current_task() {
         let mut inner = cur_tcb.inner_exclusive_access();
-        let end = start + len;
-        println!("mmap!!!");
-        inner.memory_set.mmap(start, end, prot)
+        inner.memory_set.mmap(start, len, prot, flags)
+    } else {
+        -1
+    }
+}
+
+pub fn madvise(start: usize, len: usize, advice: usize) -> isize {
+    if advice != MADV_DONTNEED || start % 4096 != 0 {
+        return -1;
+    }
+    if let Some(cur_tcb) = current_task() {
+        let mut inner = cur_tcb.inner_exclusive_access();
+        inner.memory_set.madvise_dontneed(start, len)
+    } else {
+        -1
+    }
+}
+
+pub fn memory_map(buf: usize, len: usize) -> isize {
+    if let Some(cur_tcb) = current_task() {
+        let inner = cur_tcb.inner_exclusive_access();
+        inner.memory_set.memory_map(buf, len)
     } else {
         -1
     }
 }
 
+/// Seed of the operations `mm_selftest` runs, fixed so a failure reproduces.
+const MM_SELFTEST_SEED: u64 = 0x5eed_0f_a11;
+const MM_SELFTEST_ROUNDS: usize = 256;
+/// pages of the window `mm_selftest` maps into
+const MM_SELFTEST_PAGES: usize = 32;
+
+/// Run a seeded random sequence of `mmap` and `munmap` on a fresh space and
+/// check its invariants after each of them. Returns 0, the code of the first
+/// invariant violated (see [`MemorySet::check_invariants`]), or `-6` if
+/// frames are not all given back once the space is dropped.
+pub fn mm_selftest() -> isize {
+    let free = frame_free_count();
+    let mut seed = MM_SELFTEST_SEED;
+    let mut next = |bound: usize| {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (seed >> 33) as usize % bound
+    };
+    let base: usize = 0x1000_0000;
+    let (lvpn, rvpn) = (
+        VirtAddr::from(base).floor(),
+        VirtAddr::from(base + MM_SELFTEST_PAGES * PAGE_SIZE).floor(),
+    );
+    {
+        let mut memory_set = MemorySet::new_bare();
+        for _ in 0..MM_SELFTEST_ROUNDS {
+            let start = base + next(MM_SELFTEST_PAGES - 4) * PAGE_SIZE;
+            let len = (1 + next(4)) * PAGE_SIZE - next(2) * 8;
+            // either may fail on an overlap or a hole, the invariants must hold anyway
+            if next(2) == 0 {
+                memory_set.mmap(start, len, 1 + next(3), MAP_FIXED);
+            } else {
+                memory_set.munmap(start, len);
+            }
+            if let Err(err) = memory_set.check_invariants(lvpn, rvpn) {
+                debug!("[mm_selftest] invariant {} violated", err);
+                return err;
+            }
+        }
+    }
+    if frame_free_count() != free {
+        debug!(
+            "[mm_selftest] free frames {} before, {} after",
+            free,
+            frame_free_count()
+        );
+        return -6;
+    }
+    0
+}
+
 pub fn munmap(start: usize, len: usize) -> isize {
     if len == 0 {
         return 0;
@@ -530,12 +1296,22 @@ pub fn munmap(start: usize, len: usize) -> isize {
     }
     if let Some(cur_tcb) = current_task() {
         let mut inner = cur_tcb.inner_exclusive_access();
-        inner.memory_set.munmap(start, start + len)
+        inner.memory_set.munmap(start, len)
     } else {
         -1
     }
 }
 
+/// `mmap` flags, a zero `flags` is taken as `MAP_FIXED` like before flags existed
+pub const MAP_SHARED: usize = 0x01;
+pub const MAP_PRIVATE: usize = 0x02;
+pub const MAP_FIXED: usize = 0x10;
+pub const MAP_GROWSDOWN: usize = 0x100;
+/// `madvise` advice, the only one supported
+pub const MADV_DONTNEED: usize = 4;
+/// `mmap` over the frame limit returns `-ENOMEM` rather than the usual -1
+pub const ENOMEM: isize = 12;
+
 bitflags! {
     /// map permission corresponding to that in pte: `R W X U`
     pub struct MapPermission: u8 {
@@ -567,5 +1343,956 @@ pub fn remap_test() {
         .translate(mid_data.floor())
         .unwrap()
         .executable());
+    for &(base, _) in MMIO {
+        let pte = kernel_space
+            .page_table
+            .translate(VirtAddr::from(base).floor())
+            .unwrap();
+        assert!(pte.is_valid() && pte.writable());
+        assert_eq!(pte.ppn(), PhysAddr::from(base).floor());
+    }
     info!("remap_test passed!");
 }
+
+/// Check permissions of a user space freshly built by [`MemorySet::from_elf`],
+/// run on every app loading in debug builds. The elf may come from the user,
+/// so a failed check is returned to be logged instead of asserted.
+pub fn user_remap_test(memory_set: &MemorySet) -> Result<(), &'static str> {
+    let (x, w, u) = (MapPermission::X, MapPermission::W, MapPermission::U);
+    let perm_of = |area: Option<&MapArea>| {
+        area.and_then(|area| memory_set.translate_perm(area.vpn_range.get_start()))
+            .map(|(_, perm)| perm)
+    };
+    let text = perm_of(
+        memory_set
+            .areas
+            .iter()
+            .find(|area| area.map_perm.contains(x)),
+    )
+    .ok_or("no executable segment is mapped")?;
+    if !text.contains(x) || text.contains(w) {
+        return Err("text segment is not executable-only");
+    }
+    let stack = perm_of(
+        memory_set
+            .areas
+            .iter()
+            .find(|area| area.name == Some("ustack")),
+    )
+    .ok_or("user stack is not mapped")?;
+    if !stack.contains(w) || stack.contains(x) {
+        return Err("user stack is not writable-only");
+    }
+    let (_, trap_cx) = memory_set
+        .translate_perm(VirtAddr::from(TRAP_CONTEXT).floor())
+        .ok_or("TrapContext is not mapped")?;
+    if trap_cx.contains(u) {
+        return Err("TrapContext is accessible to the user");
+    }
+    info!("user_remap_test passed!");
+    Ok(())
+}
+
+#[allow(unused)]
+pub fn frame_limit_test() {
+    let mut memory_set = MemorySet::new_bare();
+    memory_set.set_frame_limit(4);
+    let start: usize = 0x1000_0000;
+    assert_eq!(memory_set.mmap(start, 2 * PAGE_SIZE, 3, MAP_FIXED), 0);
+    assert_eq!(
+        memory_set.mmap(start + 2 * PAGE_SIZE, 2 * PAGE_SIZE, 3, MAP_FIXED),
+        0
+    );
+    assert_eq!(memory_set.frame_count(), 4);
+    // over the limit by a single page
+    assert_eq!(
+        memory_set.mmap(start + 4 * PAGE_SIZE, PAGE_SIZE, 3, MAP_FIXED),
+        -ENOMEM
+    );
+    // other errors still come first
+    assert_eq!(memory_set.mmap(start, PAGE_SIZE, 3, MAP_FIXED), -1);
+    assert_eq!(memory_set.munmap(start, 2 * PAGE_SIZE), 2);
+    assert_eq!(memory_set.frame_count(), 2);
+    assert_eq!(
+        memory_set.mmap(start + 4 * PAGE_SIZE, PAGE_SIZE, 3, MAP_FIXED),
+        0
+    );
+    info!("frame_limit_test passed!");
+}
+
+#[allow(unused)]
+pub fn copy_user_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    assert_eq!(memory_set.mmap(start, 3 * PAGE_SIZE, 3, MAP_FIXED), 0);
+    // [start + 0xf9c, start + 0x2324) crosses two page boundaries
+    let dst = start + PAGE_SIZE - 100;
+    let src: Vec<u8> = (0..5000).map(|i| (i % 251) as u8).collect();
+    assert_eq!(memory_set.copy_to_user(dst, &src), Ok(()));
+    assert_eq!(memory_set.copy_from_user(dst, src.len()), Ok(src));
+    // the last page is followed by an unmapped one
+    let tail = start + 3 * PAGE_SIZE - 10;
+    assert_eq!(memory_set.copy_to_user(tail, &[0u8; 20]), Err(-1));
+    assert_eq!(memory_set.copy_from_user(tail, 20), Err(-1));
+    // a read-only destination is refused, but can still be read
+    let ro = start + 4 * PAGE_SIZE;
+    assert_eq!(memory_set.mmap(ro, PAGE_SIZE, 1, MAP_FIXED), 0);
+    assert_eq!(memory_set.copy_to_user(ro, &[1u8; 8]), Err(-1));
+    assert_eq!(memory_set.copy_from_user(ro, 8), Ok(vec![0u8; 8]));
+    // so is TrapContext, which is not a user page at all
+    memory_set.insert_framed_area(
+        TRAP_CONTEXT.into(),
+        TRAMPOLINE.into(),
+        MapPermission::R | MapPermission::W,
+    );
+    assert_eq!(memory_set.copy_to_user(TRAP_CONTEXT, &[1u8; 8]), Err(-1));
+    assert_eq!(memory_set.copy_from_user(TRAP_CONTEXT, 8), Err(-1));
+    let trap_cx = memory_set
+        .translate(VirtAddr::from(TRAP_CONTEXT).floor())
+        .unwrap()
+        .ppn();
+    assert_eq!(trap_cx.get_bytes_array()[..8], [0u8; 8]);
+    info!("copy_user_test passed!");
+}
+
+#[allow(unused)]
+pub fn munmap_adjacent_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    assert_eq!(memory_set.mmap(start, 2 * PAGE_SIZE, 3, MAP_FIXED), 0);
+    assert_eq!(
+        memory_set.mmap(start + 2 * PAGE_SIZE, PAGE_SIZE, 3, MAP_FIXED),
+        0
+    );
+    let free_before = frame_free_count();
+    // exactly covers both areas
+    assert_eq!(memory_set.munmap(start, 3 * PAGE_SIZE), 3);
+    assert!(memory_set.areas.is_empty());
+    assert_eq!(memory_set.frame_count(), 0);
+    // the two page-table nodes above them are emptied and freed as well
+    assert_eq!(frame_free_count(), free_before + 3 + 2);
+    for i in 0..3 {
+        let vpn = VirtAddr::from(start + i * PAGE_SIZE).floor();
+        assert!(memory_set.translate(vpn).is_none());
+    }
+    // nothing is left to unmap
+    assert_eq!(memory_set.munmap(start, PAGE_SIZE), -1);
+    info!("munmap_adjacent_test passed!");
+}
+
+#[allow(unused)]
+pub fn mmap_round_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    for len in [1, PAGE_SIZE - 1, PAGE_SIZE, PAGE_SIZE + 1] {
+        let pages = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+        assert_eq!(memory_set.mmap(start, len, 3, MAP_FIXED), 0);
+        assert_eq!(memory_set.frame_count(), pages);
+        assert_eq!(memory_set.areas[0].len, len);
+        assert_eq!(memory_set.munmap(start, len), pages as isize);
+        assert!(memory_set.areas.is_empty());
+    }
+    // any length rounding to the same pages unmaps it
+    assert_eq!(memory_set.mmap(start, 100, 3, MAP_FIXED), 0);
+    assert_eq!(memory_set.munmap(start, PAGE_SIZE - 1), 1);
+    assert_eq!(memory_set.mmap(start, usize::MAX, 3, MAP_FIXED), -1);
+    info!("mmap_round_test passed!");
+}
+
+#[allow(unused)]
+pub fn page_fault_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    memory_set.insert_lazy_area(
+        start.into(),
+        (start + 2 * PAGE_SIZE).into(),
+        MapPermission::R | MapPermission::U,
+    );
+    assert_eq!(memory_set.frame_count(), 0);
+    let va = VirtAddr::from(start + PAGE_SIZE + 8);
+    assert_eq!(
+        memory_set.handle_page_fault(va, FaultCause::Load),
+        FaultResult::Resolved
+    );
+    assert_eq!(memory_set.frame_count(), 1);
+    let pte = memory_set.translate(va.floor()).unwrap();
+    assert!(pte.is_valid() && pte.readable());
+    assert!(pte.ppn().get_bytes_array().iter().all(|b| *b == 0));
+    // the first page is still not mapped
+    assert!(!memory_set
+        .translate(VirtAddr::from(start).floor())
+        .map_or(false, |pte| pte.is_valid()));
+    assert_eq!(
+        memory_set.handle_page_fault(va, FaultCause::Store),
+        FaultResult::PermissionDenied
+    );
+    assert_eq!(
+        memory_set.handle_page_fault((start + 2 * PAGE_SIZE).into(), FaultCause::Load),
+        FaultResult::Unmapped
+    );
+    // the whole area goes away, only the touched page held a frame
+    assert_eq!(memory_set.munmap(start, 2 * PAGE_SIZE), 2);
+    assert_eq!(memory_set.frame_count(), 0);
+    info!("page_fault_test passed!");
+}
+
+#[allow(unused)]
+pub fn mmap_flags_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    assert_eq!(memory_set.mmap(start, 2 * PAGE_SIZE, 3, MAP_FIXED), 0);
+    // a fixed mapping never moves
+    assert_eq!(
+        memory_set.mmap(start + PAGE_SIZE, PAGE_SIZE, 3, MAP_FIXED),
+        -1
+    );
+    assert_eq!(memory_set.mmap(start + PAGE_SIZE, PAGE_SIZE, 3, 0), -1);
+    // the closest free page to the hint is right below the area
+    let addr = memory_set.mmap(start + PAGE_SIZE, PAGE_SIZE, 3, MAP_PRIVATE);
+    assert_eq!(addr, (start - PAGE_SIZE) as isize);
+    assert!(memory_set
+        .translate(VirtAddr::from(addr as usize).floor())
+        .unwrap()
+        .is_valid());
+    // a free hint is taken as is
+    let free = start + 4 * PAGE_SIZE;
+    assert_eq!(
+        memory_set.mmap(free, PAGE_SIZE, 3, MAP_PRIVATE),
+        free as isize
+    );
+    // a hint of 0 picks an address instead of mapping the null page
+    assert_eq!(
+        memory_set.mmap(0, PAGE_SIZE, 3, MAP_PRIVATE),
+        PAGE_SIZE as isize
+    );
+    assert!(memory_set
+        .translate(VirtPageNum(0))
+        .map_or(true, |pte| !pte.is_valid()));
+    info!("mmap_flags_test passed!");
+}
+
+#[allow(unused)]
+pub fn page_table_recycle_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let baseline = frame_free_count();
+    // each page needs its own level-1 and level-0 page-table nodes
+    let stride: usize = (1 << 30) + (1 << 21);
+    for _ in 0..8 {
+        for i in 0..16 {
+            let va = 0x1000_0000 + i * stride;
+            assert_eq!(memory_set.mmap(va, PAGE_SIZE, 3, MAP_FIXED), 0);
+        }
+        assert!(frame_free_count() < baseline);
+        for i in 0..16 {
+            let va = 0x1000_0000 + i * stride;
+            assert_eq!(memory_set.munmap(va, PAGE_SIZE), 1);
+        }
+        assert_eq!(frame_free_count(), baseline);
+    }
+    info!("page_table_recycle_test passed!");
+}
+
+#[allow(unused)]
+pub fn madvise_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    assert_eq!(memory_set.mmap(start, 2 * PAGE_SIZE, 3, MAP_FIXED), 0);
+    let data = [0x5au8; 2 * PAGE_SIZE];
+    assert_eq!(memory_set.copy_to_user(start, &data), Ok(()));
+    let free_before = frame_free_count();
+    assert_eq!(memory_set.madvise_dontneed(start, 2 * PAGE_SIZE), 0);
+    // the data frames are gone but the area is kept
+    assert!(frame_free_count() >= free_before + 2);
+    assert_eq!(memory_set.frame_count(), 0);
+    assert_eq!(memory_set.areas.len(), 1);
+    assert!(!memory_set
+        .translate(VirtAddr::from(start).floor())
+        .map_or(false, |pte| pte.is_valid()));
+    // touching it again brings back a zeroed page
+    assert_eq!(
+        memory_set.handle_page_fault(start.into(), FaultCause::Store),
+        FaultResult::Resolved
+    );
+    assert_eq!(memory_set.frame_count(), 1);
+    assert_eq!(memory_set.copy_from_user(start, 16), Ok(vec![0u8; 16]));
+    // outside of any area
+    assert_eq!(
+        memory_set.madvise_dontneed(start + 2 * PAGE_SIZE, PAGE_SIZE),
+        -1
+    );
+    // nor on a page without U
+    memory_set.insert_framed_area(
+        TRAP_CONTEXT.into(),
+        TRAMPOLINE.into(),
+        MapPermission::R | MapPermission::W,
+    );
+    assert_eq!(memory_set.madvise_dontneed(TRAP_CONTEXT, PAGE_SIZE), -1);
+    assert!(memory_set
+        .translate(VirtAddr::from(TRAP_CONTEXT).floor())
+        .map_or(false, |pte| pte.is_valid()));
+    assert_eq!(memory_set.munmap(start, 2 * PAGE_SIZE), 2);
+    info!("madvise_test passed!");
+}
+
+#[allow(unused)]
+pub fn mmap_quiet_test() {
+    let level = log::max_level();
+    log::set_max_level(LevelFilter::Off);
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    assert_eq!(memory_set.mmap(start, 2 * PAGE_SIZE, 3, MAP_FIXED), 0);
+    assert_eq!(memory_set.mmap(start, PAGE_SIZE, 3, MAP_FIXED), -1);
+    assert_eq!(
+        memory_set.mmap(start, PAGE_SIZE, 3, MAP_PRIVATE),
+        (start - PAGE_SIZE) as isize
+    );
+    assert_eq!(memory_set.munmap(start, 2 * PAGE_SIZE), 2);
+    assert_eq!(memory_set.munmap(start, 2 * PAGE_SIZE), -1);
+    log::set_max_level(level);
+    info!("mmap_quiet_test passed!");
+}
+
+#[allow(unused)]
+pub fn share_readonly_test() {
+    let elf_data = get_app_data_by_name("ch5b_initproc").unwrap();
+    let (parent, _, _) = MemorySet::from_elf(elf_data).unwrap();
+    let child = MemorySet::from_existed_user(&parent);
+    let ppn_pair = |vpn: VirtPageNum| {
+        (
+            parent.translate(vpn).unwrap().ppn(),
+            child.translate(vpn).unwrap().ppn(),
+        )
+    };
+    let text = parent
+        .areas
+        .iter()
+        .find(|area| area.map_perm.contains(MapPermission::X))
+        .unwrap();
+    for vpn in text.vpn_range {
+        let (parent_ppn, child_ppn) = ppn_pair(vpn);
+        assert_eq!(parent_ppn, child_ppn);
+    }
+    let stack = parent
+        .areas
+        .iter()
+        .filter(|area| area.vpn_range.get_end() <= VirtAddr::from(TRAP_CONTEXT).floor())
+        .max_by_key(|area| area.vpn_range.get_start())
+        .unwrap();
+    for vpn in stack.vpn_range {
+        let (parent_ppn, child_ppn) = ppn_pair(vpn);
+        assert_ne!(parent_ppn, child_ppn);
+    }
+    // shared frames outlive the parent
+    let text_start = text.vpn_range.get_start();
+    let text_ppn = child.translate(text_start).unwrap().ppn();
+    drop(parent);
+    assert_eq!(child.translate(text_start).unwrap().ppn(), text_ppn);
+    info!("share_readonly_test passed!");
+}
+
+/// Build a minimal RISC-V elf with a single R|X LOAD segment,
+/// whose file data is the elf itself padded to `file_size` bytes.
+#[allow(unused)]
+fn craft_elf(vaddr: u64, file_size: u64, mem_size: u64) -> Vec<u8> {
+    let mut elf = Vec::new();
+    // elf header
+    elf.extend_from_slice(&[0x7f, 0x45, 0x4c, 0x46, 2, 1, 1, 0]);
+    elf.extend_from_slice(&[0; 8]);
+    elf.extend_from_slice(&2u16.to_le_bytes()); // ET_EXEC
+    elf.extend_from_slice(&0xf3u16.to_le_bytes()); // EM_RISCV
+    elf.extend_from_slice(&1u32.to_le_bytes());
+    elf.extend_from_slice(&vaddr.to_le_bytes()); // entry
+    elf.extend_from_slice(&64u64.to_le_bytes()); // phoff
+    elf.extend_from_slice(&0u64.to_le_bytes()); // shoff
+    elf.extend_from_slice(&0u32.to_le_bytes());
+    elf.extend_from_slice(&64u16.to_le_bytes()); // ehsize
+    elf.extend_from_slice(&56u16.to_le_bytes()); // phentsize
+    elf.extend_from_slice(&1u16.to_le_bytes()); // phnum
+    elf.extend_from_slice(&64u16.to_le_bytes());
+    elf.extend_from_slice(&[0; 4]);
+    // program header
+    elf.extend_from_slice(&1u32.to_le_bytes()); // PT_LOAD
+    elf.extend_from_slice(&5u32.to_le_bytes()); // R|X
+    elf.extend_from_slice(&0u64.to_le_bytes()); // offset
+    elf.extend_from_slice(&vaddr.to_le_bytes());
+    elf.extend_from_slice(&vaddr.to_le_bytes());
+    elf.extend_from_slice(&file_size.to_le_bytes());
+    elf.extend_from_slice(&mem_size.to_le_bytes());
+    elf.extend_from_slice(&(PAGE_SIZE as u64).to_le_bytes());
+    if elf.len() < file_size as usize {
+        elf.resize(file_size as usize, 0);
+    }
+    elf
+}
+
+#[allow(unused)]
+pub fn load_overlap_test() {
+    let free_before = frame_free_count();
+    let page = PAGE_SIZE as u64;
+    let trap_cx = TRAP_CONTEXT as u64;
+    // segment running into TrapContext
+    let elf = craft_elf(trap_cx - page, 0, 2 * page);
+    assert_eq!(
+        MemorySet::from_elf(&elf).err(),
+        Some(LoadError::OverlapsTrapContext)
+    );
+    // segment covering the trampoline and wrapping around
+    let elf = craft_elf(TRAMPOLINE as u64, 0, u64::MAX);
+    assert_eq!(
+        MemorySet::from_elf(&elf).err(),
+        Some(LoadError::OverlapsTrapContext)
+    );
+    // segment fits, but the user stack above it would not
+    let user_end = USER_SPACE_END as u64;
+    let elf = craft_elf(user_end - 2 * page, 0, page);
+    assert_eq!(
+        MemorySet::from_elf(&elf).err(),
+        Some(LoadError::OverlapsTrapContext)
+    );
+    // segments past the user space alias TrapContext and the trampoline
+    for vaddr in [0x7f_ffff_e000, 0x7f_ffff_f000] {
+        let elf = craft_elf(vaddr, 0, page);
+        assert_eq!(
+            MemorySet::from_elf(&elf).err(),
+            Some(LoadError::OverlapsTrapContext)
+        );
+    }
+    let mut memory_set = MemorySet::new_bare();
+    assert_eq!(memory_set.mmap(0x7f_ffff_e000, PAGE_SIZE, 3, MAP_FIXED), -1);
+    assert_eq!(
+        memory_set.mmap(USER_SPACE_END - PAGE_SIZE, PAGE_SIZE, 3, MAP_FIXED),
+        0
+    );
+    drop(memory_set);
+    // rejected spaces are torn down without leaking frames
+    assert_eq!(frame_free_count(), free_before);
+    let elf = craft_elf(0x10000, 0, page);
+    assert!(MemorySet::from_elf(&elf).is_ok());
+    info!("load_overlap_test passed!");
+}
+
+#[allow(unused)]
+pub fn memory_map_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    assert_eq!(memory_set.mmap(start, PAGE_SIZE, 1, MAP_FIXED), 0);
+    assert_eq!(
+        memory_set.mmap(start + 2 * PAGE_SIZE, 2 * PAGE_SIZE, 3, MAP_FIXED),
+        0
+    );
+    // the buffer is an area of its own and gets reported too
+    let buf = start + 8 * PAGE_SIZE;
+    assert_eq!(memory_set.mmap(buf, PAGE_SIZE, 3, MAP_FIXED), 0);
+    let (r, w, u) = (MapPermission::R, MapPermission::W, MapPermission::U);
+    let expected = [
+        (start, start + PAGE_SIZE, r | u),
+        (start + 2 * PAGE_SIZE, start + 4 * PAGE_SIZE, r | w | u),
+        (buf, buf + PAGE_SIZE, r | w | u),
+    ];
+    assert_eq!(memory_set.memory_map(buf, 8), 3);
+    let size = core::mem::size_of::<MapRecord>();
+    let bytes = memory_set.copy_from_user(buf, 3 * size).unwrap();
+    for (i, (start_va, end_va, perm)) in expected.iter().enumerate() {
+        let record = unsafe { (bytes[i * size..].as_ptr() as *const MapRecord).read_unaligned() };
+        assert_eq!(
+            record,
+            MapRecord {
+                start_va: *start_va,
+                end_va: *end_va,
+                perm: perm.bits() as usize,
+                map_type: MapType::Framed as usize,
+                name: *b"mmap\0\0\0\0",
+            }
+        );
+    }
+    // a short buffer only gets the leading records
+    assert_eq!(memory_set.memory_map(buf, 1), 1);
+    // the buffer must be mapped
+    assert_eq!(memory_set.memory_map(start + 6 * PAGE_SIZE, 8), -1);
+    // and writable for the user, which TrapContext is not
+    memory_set.insert_framed_area(
+        TRAP_CONTEXT.into(),
+        TRAMPOLINE.into(),
+        MapPermission::R | MapPermission::W,
+    );
+    assert_eq!(memory_set.memory_map(TRAP_CONTEXT + 8, 8), -1);
+    // nor is a read-only area
+    assert_eq!(memory_set.memory_map(start, 1), -1);
+    info!("memory_map_test passed!");
+}
+
+#[allow(unused)]
+pub fn stack_guard_test() {
+    let elf_data = get_app_data_by_name("ch5b_initproc").unwrap();
+    let (mut memory_set, user_sp, _) = MemorySet::from_elf(elf_data).unwrap();
+    let stack_bottom = user_sp - USER_STACK_SIZE;
+    let guard = stack_bottom - PAGE_SIZE;
+    // the guard page is not covered by any area
+    assert!(!memory_set.areas.iter().any(|area| {
+        let vpn = VirtAddr::from(guard).floor();
+        area.vpn_range.get_start() <= vpn && vpn < area.vpn_range.get_end()
+    }));
+    assert_eq!(memory_set.mmap(guard, PAGE_SIZE, 3, MAP_FIXED), -1);
+    assert_eq!(memory_set.mmap(guard + 8, 16, 3, MAP_FIXED), -1);
+    // a hint right at the guard page is moved away from it
+    let va = memory_set.mmap(guard, PAGE_SIZE, 3, MAP_PRIVATE);
+    assert!(va > 0 && va as usize != guard);
+    // the guard page goes along with its stack
+    assert_eq!(
+        memory_set.munmap(stack_bottom, USER_STACK_SIZE),
+        (USER_STACK_SIZE / PAGE_SIZE) as isize
+    );
+    assert_eq!(memory_set.mmap(guard, PAGE_SIZE, 3, MAP_FIXED), 0);
+    info!("stack_guard_test passed!");
+}
+
+#[allow(unused)]
+pub fn copy_data_at_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    memory_set.insert_framed_area(
+        start.into(),
+        (start + 3 * PAGE_SIZE).into(),
+        MapPermission::R | MapPermission::W | MapPermission::U,
+    );
+    // [PAGE_SIZE + 100, 2 * PAGE_SIZE + 100) only touches pages 1 and 2
+    let offset = PAGE_SIZE + 100;
+    let data: Vec<u8> = (0..PAGE_SIZE).map(|i| (i % 251) as u8 + 1).collect();
+    assert_eq!(
+        memory_set.areas[0].copy_data_at(&mut memory_set.page_table, offset, &data),
+        Ok(())
+    );
+    assert_eq!(
+        memory_set.copy_from_user(start, offset),
+        Ok(vec![0u8; offset])
+    );
+    assert_eq!(
+        memory_set.copy_from_user(start + offset, data.len()),
+        Ok(data)
+    );
+    let tail = offset + PAGE_SIZE;
+    assert_eq!(
+        memory_set.copy_from_user(start + tail, 3 * PAGE_SIZE - tail),
+        Ok(vec![0u8; 3 * PAGE_SIZE - tail])
+    );
+    info!("copy_data_at_test passed!");
+}
+
+#[allow(unused)]
+pub fn copy_into_page_test() {
+    let frame = frame_alloc().unwrap();
+    assert_eq!(copy_into_page(frame.ppn, PAGE_SIZE - 2, &[1, 2]), Ok(()));
+    assert_eq!(copy_into_page(frame.ppn, PAGE_SIZE - 1, &[1, 2]), Err(-1));
+    assert_eq!(copy_into_page(frame.ppn, usize::MAX, &[1]), Err(-1));
+    assert_eq!(frame.ppn.get_bytes_array()[PAGE_SIZE - 2..], [1, 2]);
+    // file data one page plus a bit, but memory for a single page only
+    let free_before = frame_free_count();
+    let page = PAGE_SIZE as u64;
+    let elf = craft_elf(0x10000, page + 16, page);
+    assert_eq!(
+        MemorySet::from_elf(&elf).err(),
+        Some(LoadError::DataOverflow)
+    );
+    assert_eq!(frame_free_count(), free_before);
+    // the same data fits once the segment spans two pages
+    let elf = craft_elf(0x10000, page + 16, 2 * page);
+    let (memory_set, _, _) = MemorySet::from_elf(&elf).unwrap();
+    assert_eq!(
+        memory_set.copy_from_user(0x10000, elf.len()),
+        Ok(elf.clone())
+    );
+    info!("copy_into_page_test passed!");
+}
+
+#[allow(unused)]
+pub fn frame_uninit_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    // leave garbage in the frame handed out next
+    let frame = frame_alloc().unwrap();
+    frame.ppn.get_bytes_array().fill(0xff);
+    drop(frame);
+    let uninit_before = frame_uninit_count();
+    assert_eq!(memory_set.mmap(start, PAGE_SIZE, 3, MAP_FIXED), 0);
+    assert_eq!(frame_uninit_count(), uninit_before);
+    assert_eq!(
+        memory_set.copy_from_user(start, PAGE_SIZE),
+        Ok(vec![0u8; PAGE_SIZE])
+    );
+    // two full pages of file data, then 16 bytes and zeroes up to the third
+    let page = PAGE_SIZE as u64;
+    let elf = craft_elf(0x10000, 2 * page + 16, 3 * page);
+    let (memory_set, _, _) = MemorySet::from_elf(&elf).unwrap();
+    assert_eq!(frame_uninit_count(), uninit_before + 2);
+    assert_eq!(
+        memory_set.copy_from_user(0x10000, elf.len()),
+        Ok(elf.clone())
+    );
+    assert_eq!(
+        memory_set.copy_from_user(0x10000 + elf.len(), PAGE_SIZE - 16),
+        Ok(vec![0u8; PAGE_SIZE - 16])
+    );
+    info!("frame_uninit_test passed!");
+}
+
+#[allow(unused)]
+pub fn check_range_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    assert_eq!(memory_set.mmap(start, 2 * PAGE_SIZE, 3, MAP_FIXED), 0);
+    assert_eq!(
+        memory_set.mmap(start + 2 * PAGE_SIZE, PAGE_SIZE, 1, MAP_FIXED),
+        0
+    );
+    let (r, w, u) = (MapPermission::R, MapPermission::W, MapPermission::U);
+    // fully valid RW range across a page boundary
+    assert!(memory_set.check_range(start + 100, PAGE_SIZE, r | w | u));
+    // the third page lacks W
+    assert!(memory_set.check_range(start + 2 * PAGE_SIZE, PAGE_SIZE, r | u));
+    assert!(!memory_set.check_range(start + PAGE_SIZE, 2 * PAGE_SIZE, r | w | u));
+    // the range runs into an unmapped page
+    assert!(!memory_set.check_range(start + 3 * PAGE_SIZE - 8, 16, r | u));
+    assert!(!memory_set.check_range(usize::MAX, 2, r));
+    assert!(memory_set.check_range(start + 3 * PAGE_SIZE, 0, r | w | u));
+    info!("check_range_test passed!");
+}
+
+#[allow(unused)]
+pub fn growsdown_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    let flags = MAP_FIXED | MAP_GROWSDOWN;
+    assert_eq!(memory_set.mmap(start, 4 * PAGE_SIZE, 3, flags), 0);
+    // only the top page is there at first
+    let top = VirtAddr::from(start + 3 * PAGE_SIZE).floor();
+    assert_eq!(memory_set.areas[0].vpn_range.get_start(), top);
+    assert_eq!(memory_set.frame_count(), 1);
+    let va = VirtAddr::from(start + 2 * PAGE_SIZE + 8);
+    assert_eq!(
+        memory_set.handle_page_fault(va, FaultCause::Store),
+        FaultResult::Resolved
+    );
+    assert_eq!(memory_set.areas[0].vpn_range.get_start(), va.floor());
+    assert_eq!(memory_set.frame_count(), 2);
+    assert!(memory_set.check_range(
+        va.into(),
+        8,
+        MapPermission::R | MapPermission::W | MapPermission::U
+    ));
+    // never below the reserved range
+    assert_eq!(
+        memory_set.handle_page_fault((start - PAGE_SIZE).into(), FaultCause::Store),
+        FaultResult::Unmapped
+    );
+    // nor past an area which took the room below
+    assert_eq!(
+        memory_set.mmap(start + PAGE_SIZE, PAGE_SIZE, 3, MAP_FIXED),
+        0
+    );
+    assert_eq!(
+        memory_set.handle_page_fault(start.into(), FaultCause::Store),
+        FaultResult::Unmapped
+    );
+    assert_eq!(memory_set.areas[0].vpn_range.get_start(), va.floor());
+    info!("growsdown_test passed!");
+}
+
+#[allow(unused)]
+pub fn area_name_test() {
+    let elf_data = get_app_data_by_name("ch5b_initproc").unwrap();
+    let (memory_set, user_sp, _) = MemorySet::from_elf(elf_data).unwrap();
+    let stack = memory_set
+        .areas
+        .iter()
+        .find(|area| area.name == Some("ustack"))
+        .unwrap();
+    assert_eq!(VirtAddr::from(stack.vpn_range.get_end()).0, user_sp);
+    let trap_cx = memory_set.areas.last().unwrap();
+    assert_eq!(trap_cx.name, Some("trapctx"));
+    assert_eq!(
+        trap_cx.vpn_range.get_start(),
+        VirtAddr::from(TRAP_CONTEXT).floor()
+    );
+    assert!(memory_set.areas[..memory_set.areas.len() - 2]
+        .iter()
+        .all(|area| area.name == Some("elf")));
+    info!("area_name_test passed!");
+}
+
+#[allow(unused)]
+pub fn stack_size_test() {
+    let elf_data = get_app_data_by_name("ch5b_initproc").unwrap();
+    let stack_size = 64 * 1024;
+    let (memory_set, user_sp, _) = MemorySet::from_elf_with_stack(elf_data, stack_size).unwrap();
+    let stack = memory_set
+        .areas
+        .iter()
+        .find(|area| area.name == Some("ustack"))
+        .unwrap();
+    let (start, end) = (stack.vpn_range.get_start(), stack.vpn_range.get_end());
+    assert_eq!(end.0 - start.0, stack_size / PAGE_SIZE);
+    assert_eq!(VirtAddr::from(end).0, user_sp);
+    // the guard page is still right below it
+    let guard = VirtPageNum(start.0 - 1);
+    assert_eq!(stack.stack_guard, Some(guard));
+    assert!(!memory_set
+        .translate(guard)
+        .map_or(false, |pte| pte.is_valid()));
+    // sizes are rounded up to pages
+    let (memory_set, _, _) = MemorySet::from_elf_with_stack(elf_data, PAGE_SIZE + 1).unwrap();
+    let stack = memory_set
+        .areas
+        .iter()
+        .find(|area| area.name == Some("ustack"))
+        .unwrap();
+    assert_eq!(
+        stack.vpn_range.get_end().0 - stack.vpn_range.get_start().0,
+        2
+    );
+    assert_eq!(
+        MemorySet::from_elf_with_stack(elf_data, 0).err(),
+        Some(LoadError::BadStackSize)
+    );
+    assert_eq!(
+        MemorySet::from_elf_with_stack(elf_data, usize::MAX).err(),
+        Some(LoadError::BadStackSize)
+    );
+    info!("stack_size_test passed!");
+}
+
+#[allow(unused)]
+pub fn munmap_trim_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    assert_eq!(memory_set.mmap(start, 6 * PAGE_SIZE, 3, MAP_FIXED), 0);
+    assert_eq!(memory_set.frame_count(), 6);
+    let free = frame_free_count();
+    // a hole in the middle is refused
+    assert_eq!(memory_set.munmap(start + 2 * PAGE_SIZE, 2 * PAGE_SIZE), -1);
+    // so is TrapContext, or any other area without U
+    memory_set.insert_framed_area(
+        TRAP_CONTEXT.into(),
+        TRAMPOLINE.into(),
+        MapPermission::R | MapPermission::W,
+    );
+    assert_eq!(memory_set.munmap(TRAP_CONTEXT, PAGE_SIZE), -1);
+    memory_set.remove_area_with_start_vpn(VirtAddr::from(TRAP_CONTEXT).floor());
+    assert_eq!(memory_set.munmap(start, 2 * PAGE_SIZE), 2);
+    assert_eq!(memory_set.munmap(start + 4 * PAGE_SIZE, 2 * PAGE_SIZE), 2);
+    assert_eq!(memory_set.areas.len(), 1);
+    let area = &memory_set.areas[0];
+    assert_eq!(
+        area.vpn_range.get_start(),
+        VirtAddr::from(start + 2 * PAGE_SIZE).floor()
+    );
+    assert_eq!(
+        area.vpn_range.get_end(),
+        VirtAddr::from(start + 4 * PAGE_SIZE).floor()
+    );
+    assert_eq!(area.data_frames.len(), 2);
+    assert_eq!(area.len, 2 * PAGE_SIZE);
+    assert_eq!(memory_set.frame_count(), 2);
+    assert_eq!(frame_free_count(), free + 4);
+    let rw = MapPermission::R | MapPermission::W | MapPermission::U;
+    assert!(memory_set.check_range(start + 2 * PAGE_SIZE, 2 * PAGE_SIZE, rw));
+    assert!(!memory_set.check_range(start, PAGE_SIZE, rw));
+    assert!(!memory_set.check_range(start + 4 * PAGE_SIZE, PAGE_SIZE, rw));
+    // an elf-like area shorter than its pages keeps a len within them
+    let start = start + 8 * PAGE_SIZE;
+    memory_set.insert_framed_area(
+        (start + PAGE_SIZE - 0x100).into(),
+        (start + PAGE_SIZE + 0x100).into(),
+        rw,
+    );
+    assert_eq!(memory_set.munmap(start, PAGE_SIZE), 1);
+    assert_eq!(memory_set.areas[1].len, 0x200);
+    // a trimmed MAP_GROWSDOWN area no longer grows back into its head
+    let start = start + 8 * PAGE_SIZE;
+    let flags = MAP_FIXED | MAP_GROWSDOWN;
+    assert_eq!(memory_set.mmap(start, 4 * PAGE_SIZE, 3, flags), 0);
+    let va = VirtAddr::from(start + 2 * PAGE_SIZE);
+    assert_eq!(
+        memory_set.handle_page_fault(va, FaultCause::Store),
+        FaultResult::Resolved
+    );
+    assert_eq!(memory_set.munmap(va.into(), PAGE_SIZE), 1);
+    assert_eq!(
+        memory_set.handle_page_fault(va, FaultCause::Store),
+        FaultResult::Unmapped
+    );
+    info!("munmap_trim_test passed!");
+}
+
+#[allow(unused)]
+pub fn translate_perm_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    assert_eq!(memory_set.mmap(start, PAGE_SIZE, 3, MAP_FIXED), 0);
+    let vpn = VirtAddr::from(start).floor();
+    let ppn = memory_set.translate(vpn).unwrap().ppn();
+    assert_eq!(
+        memory_set.translate_perm(vpn),
+        Some((ppn, MapPermission::R | MapPermission::W | MapPermission::U))
+    );
+    assert_eq!(
+        memory_set.translate_perm(VirtAddr::from(start + PAGE_SIZE).floor()),
+        None
+    );
+    info!("translate_perm_test passed!");
+}
+
+#[allow(unused)]
+pub fn mm_invariants_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    let (lvpn, rvpn) = (
+        VirtAddr::from(start).floor(),
+        VirtAddr::from(start + 8 * PAGE_SIZE).floor(),
+    );
+    assert_eq!(memory_set.mmap(start, 4 * PAGE_SIZE, 3, MAP_FIXED), 0);
+    assert_eq!(memory_set.munmap(start, PAGE_SIZE), 1);
+    assert_eq!(memory_set.check_invariants(lvpn, rvpn), Ok(()));
+    // an area slipped in behind mmap's back breaks them
+    memory_set.areas.push(MapArea::new(
+        (start + 3 * PAGE_SIZE).into(),
+        (start + 5 * PAGE_SIZE).into(),
+        MapType::Framed,
+        MapPermission::R | MapPermission::U,
+    ));
+    assert_eq!(memory_set.check_areas_disjoint(), Err(-3));
+    assert_eq!(memory_set.check_translate(lvpn, rvpn), Ok(()));
+    memory_set.areas.pop();
+    memory_set.frame_count += 1;
+    assert_eq!(memory_set.check_frame_count(), Err(-5));
+    memory_set.frame_count -= 1;
+    assert_eq!(mm_selftest(), 0);
+    info!("mm_invariants_test passed!");
+}
+
+static WRITEBACKS: AtomicUsize = AtomicUsize::new(0);
+static WRITEBACK_VPN: AtomicUsize = AtomicUsize::new(0);
+
+fn count_writeback(vpn: VirtPageNum, data: &[u8]) {
+    assert_eq!(data[0], 0x5a);
+    WRITEBACKS.fetch_add(1, Ordering::Relaxed);
+    WRITEBACK_VPN.store(vpn.0, Ordering::Relaxed);
+}
+
+/// Mark `vpn` dirty as if the hardware had written to it.
+fn set_dirty(memory_set: &mut MemorySet, vpn: VirtPageNum) {
+    let pte = memory_set.translate(vpn).unwrap();
+    pte.ppn().get_bytes_array()[0] = 0x5a;
+    memory_set.page_table.unmap(vpn);
+    memory_set
+        .page_table
+        .map(vpn, pte.ppn(), pte.flags() | PTEFlags::D);
+}
+
+#[allow(unused)]
+pub fn writeback_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    let flags = MAP_FIXED | MAP_SHARED;
+    assert_eq!(memory_set.mmap(start, 2 * PAGE_SIZE, 3, flags), 0);
+    assert_eq!(memory_set.set_writeback(start, count_writeback), Ok(()));
+    assert_eq!(
+        memory_set.set_writeback(start + PAGE_SIZE, count_writeback),
+        Err(-1)
+    );
+    let dirty = VirtAddr::from(start + PAGE_SIZE).floor();
+    set_dirty(&mut memory_set, dirty);
+    let before = WRITEBACKS.load(Ordering::Relaxed);
+    // the clean page is dropped without a writeback
+    assert_eq!(memory_set.munmap(start, 2 * PAGE_SIZE), 2);
+    assert_eq!(WRITEBACKS.load(Ordering::Relaxed), before + 1);
+    assert_eq!(WRITEBACK_VPN.load(Ordering::Relaxed), dirty.0);
+    // a dirty page of a private map goes silently
+    assert_eq!(memory_set.mmap(start, PAGE_SIZE, 3, MAP_FIXED), 0);
+    assert_eq!(memory_set.set_writeback(start, count_writeback), Ok(()));
+    set_dirty(&mut memory_set, VirtAddr::from(start).floor());
+    assert_eq!(memory_set.munmap(start, PAGE_SIZE), 1);
+    assert_eq!(WRITEBACKS.load(Ordering::Relaxed), before + 1);
+    info!("writeback_test passed!");
+}
+
+#[allow(unused)]
+pub fn iter_mappings_test() {
+    let elf_data = get_app_data_by_name("ch5b_initproc").unwrap();
+    let (memory_set, _, _) = MemorySet::from_elf(elf_data).unwrap();
+    let mappings: Vec<(VirtPageNum, PhysPageNum, MapPermission)> =
+        memory_set.iter_mappings().collect();
+    let pages: usize = memory_set
+        .areas
+        .iter()
+        .map(|area| area.vpn_range.get_end().0 - area.vpn_range.get_start().0)
+        .sum();
+    assert_eq!(mappings.len(), pages);
+    let trampoline = VirtAddr::from(TRAMPOLINE).floor();
+    for &(vpn, ppn, perm) in mappings.iter() {
+        assert!(vpn != trampoline);
+        assert_eq!(memory_set.translate_perm(vpn), Some((ppn, perm)));
+        let area = memory_set
+            .areas
+            .iter()
+            .find(|area| area.vpn_range.get_start() <= vpn && vpn < area.vpn_range.get_end())
+            .unwrap();
+        assert_eq!(perm, area.map_perm);
+    }
+    let trap_cx = VirtAddr::from(TRAP_CONTEXT).floor();
+    assert!(mappings
+        .iter()
+        .any(|&(vpn, _, perm)| vpn == trap_cx && perm == MapPermission::R | MapPermission::W));
+    info!("iter_mappings_test passed!");
+}
+
+#[allow(unused)]
+pub fn load_malformed_test() {
+    let free_before = frame_free_count();
+    let elf = craft_elf(0x10000, 0, PAGE_SIZE as u64);
+    assert!(MemorySet::from_elf(&elf).is_ok());
+    assert_eq!(MemorySet::from_elf(&[]).err(), Some(LoadError::BadMagic));
+    let mut bad_magic = elf.clone();
+    bad_magic[1] = 0;
+    assert_eq!(
+        MemorySet::from_elf(&bad_magic).err(),
+        Some(LoadError::BadMagic)
+    );
+    // cut in the elf header, then in the program header table
+    assert_eq!(
+        MemorySet::from_elf(&elf[..40]).err(),
+        Some(LoadError::BadHeader)
+    );
+    assert_eq!(
+        MemorySet::from_elf(&elf[..80]).err(),
+        Some(LoadError::BadProgramHeader)
+    );
+    // ph_count far past the end of the data
+    let mut bad_count = elf.clone();
+    bad_count[56..58].copy_from_slice(&0xffffu16.to_le_bytes());
+    assert_eq!(
+        MemorySet::from_elf(&bad_count).err(),
+        Some(LoadError::BadProgramHeader)
+    );
+    // file data of the segment past the end of the data
+    let mut bad_offset = elf.clone();
+    bad_offset[72..80].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert_eq!(
+        MemorySet::from_elf(&bad_offset).err(),
+        Some(LoadError::BadProgramHeader)
+    );
+    // the same LOAD segment twice
+    let mut twice = elf.clone();
+    twice[56..58].copy_from_slice(&2u16.to_le_bytes());
+    let ph = twice[64..120].to_vec();
+    twice.extend_from_slice(&ph);
+    assert_eq!(
+        MemorySet::from_elf(&twice).err(),
+        Some(LoadError::OverlappingSegments)
+    );
+    // moved below the first one it is fine, and the stack stays above both
+    twice[120 + 16..120 + 32].copy_from_slice(&[0x8000u64.to_le_bytes(); 2].concat());
+    let (_, user_sp, _) = MemorySet::from_elf(&twice).unwrap();
+    assert_eq!(user_sp, 0x11000 + PAGE_SIZE + USER_STACK_SIZE);
+    assert_eq!(frame_free_count(), free_before);
+    info!("load_malformed_test passed!");
+}
@@ -13,33 +13,31 @@ mod heap_allocator;
 mod memory_set;
 mod page_table;
 
+pub use address::vpn_range_test;
+use address::{copy_into_page, StepByOne, VPNRange};
 pub use address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
-use address::{StepByOne, VPNRange};
-pub use frame_allocator::{frame_alloc, FrameTracker};
-pub use memory_set::remap_test;
-pub use memory_set::{MapPermission, MemorySet, KERNEL_SPACE,mmap,munmap};
+pub use frame_allocator::frame_double_free_test;
+pub use frame_allocator::{
+    frame_alloc, frame_alloc_uninit, frame_free_count, frame_uninit_count, FrameTracker,
+};
+pub use memory_set::{
+    area_name_test, check_range_test, copy_data_at_test, copy_into_page_test, copy_user_test,
+    frame_limit_test, frame_uninit_test, growsdown_test, iter_mappings_test, load_malformed_test,
+    load_overlap_test, madvise_test, memory_map_test, mm_invariants_test, mmap_flags_test,
+    mmap_quiet_test, mmap_round_test, munmap_adjacent_test, munmap_trim_test, page_fault_test,
+    page_table_recycle_test, remap_test, share_readonly_test, stack_guard_test, stack_size_test,
+    translate_perm_test, writeback_test,
+};
+pub use memory_set::{
+    madvise, memory_map, mm_selftest, mmap, munmap, FaultCause, FaultResult, LoadError,
+    MapPermission, MemorySet, KERNEL_SPACE,
+};
 pub use page_table::{translated_byte_buffer, translated_refmut, translated_str, PageTableEntry};
 use page_table::{PTEFlags, PageTable};
 
-use crate::task::current_user_token;
-
 /// initiate heap allocator, frame allocator and kernel space
 pub fn init() {
     heap_allocator::init_heap();
     frame_allocator::init_frame_allocator();
     KERNEL_SPACE.exclusive_access().activate();
 }
-
-pub fn get_slice_buffer<T: 'static>(start: usize) -> Option<&'static mut T> {
-    let satp = current_user_token();
-    let va = VirtAddr::from(start);
-    let vpn = va.floor();
-    let pt = PageTable::from_token(satp);
-    if let Some(pte) = pt.translate(vpn) {
-        let ppn = pte.ppn();
-        let pa = PhysAddr::from(PhysAddr::from(ppn).0 | va.page_offset());
-        Some(pa.get_mut::<T>())
-    } else {
-        None
-    }
-}
\ No newline at end of file
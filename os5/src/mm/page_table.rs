@@ -54,6 +54,9 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+    pub fn dirty(&self) -> bool {
+        (self.flags() & PTEFlags::D) != PTEFlags::empty()
+    }
 }
 
 /// page table structure
@@ -120,11 +123,36 @@ impl PageTable {
         assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
         *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
     }
+    /// Page-table nodes left without any valid entry are freed, except the root.
     #[allow(unused)]
     pub fn unmap(&mut self, vpn: VirtPageNum) {
-        let pte = self.find_pte_create(vpn).unwrap();
+        let idxs = vpn.indexes();
+        // nodes on the path from root to the leaf
+        let mut ppns = [self.root_ppn; 3];
+        for i in 0..2 {
+            let pte = ppns[i].get_pte_array()[idxs[i]];
+            assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
+            ppns[i + 1] = pte.ppn();
+        }
+        let pte = &mut ppns[2].get_pte_array()[idxs[2]];
         assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
         *pte = PageTableEntry::empty();
+        for i in (1..3).rev() {
+            if ppns[i].get_pte_array().iter().any(|pte| pte.is_valid()) {
+                break;
+            }
+            ppns[i - 1].get_pte_array()[idxs[i - 1]] = PageTableEntry::empty();
+            self.free_node(ppns[i]);
+        }
+    }
+    fn free_node(&mut self, ppn: PhysPageNum) {
+        if let Some(idx) = self.frames.iter().position(|frame| frame.ppn == ppn) {
+            self.frames.swap_remove(idx);
+        }
+    }
+    /// Free every node including the root, the table must not be used afterwards.
+    pub fn recycle(&mut self) {
+        self.frames.clear();
     }
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.find_pte(vpn).copied()
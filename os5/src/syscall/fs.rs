@@ -1,8 +1,8 @@
 //! File and filesystem-related syscalls
 
-use crate::mm::translated_byte_buffer;
+use crate::mm::{translated_byte_buffer, MapPermission};
 use crate::sbi::console_getchar;
-use crate::task::{current_user_token, suspend_current_and_run_next};
+use crate::task::{current_task, current_user_token, suspend_current_and_run_next};
 
 const FD_STDIN: usize = 0;
 const FD_STDOUT: usize = 1;
@@ -10,6 +10,15 @@ const FD_STDOUT: usize = 1;
 pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
     match fd {
         FD_STDOUT => {
+            let task = current_task().unwrap();
+            let readable = task.inner_exclusive_access().memory_set.check_range(
+                buf as usize,
+                len,
+                MapPermission::R | MapPermission::U,
+            );
+            if !readable {
+                return -1;
+            }
             let buffers = translated_byte_buffer(current_user_token(), buf, len);
             for buffer in buffers {
                 print!("{}", core::str::from_utf8(buffer).unwrap());
@@ -15,6 +15,7 @@ const SYSCALL_WRITE: usize = 64;
 const SYSCALL_EXIT: usize = 93;
 const SYSCALL_YIELD: usize = 124;
 const SYSCALL_GET_TIME: usize = 169;
+const SYSCALL_SETRLIMIT: usize = 164;
 const SYSCALL_GETPID: usize = 172;
 const SYSCALL_FORK: usize = 220;
 const SYSCALL_EXEC: usize = 221;
@@ -22,8 +23,15 @@ const SYSCALL_WAITPID: usize = 260;
 const SYSCALL_SPAWN: usize = 400;
 const SYSCALL_MUNMAP: usize = 215;
 const SYSCALL_MMAP: usize = 222;
+const SYSCALL_MADVISE: usize = 233;
 const SYSCALL_SET_PRIORITY: usize = 140;
 const SYSCALL_TASK_INFO: usize = 410;
+const SYSCALL_MEMORY_MAP: usize = 411;
+const SYSCALL_MM_SELFTEST: usize = 412;
+/// `mmap` taking `flags` in a3, plain `mmap` only passes three registers
+const SYSCALL_MMAP_FLAGS: usize = 413;
+/// `munmap` returning the number of pages unmapped, plain `munmap` returns 0
+const SYSCALL_MUNMAP_COUNT: usize = 414;
 
 mod fs;
 mod process;
@@ -32,7 +40,7 @@ use fs::*;
 use process::*;
 pub use process::TaskInfo;
 /// handle syscall exception with `syscall_id` and other arguments
-pub fn syscall(syscall_id: usize, args: [usize; 3]) -> isize {
+pub fn syscall(syscall_id: usize, args: [usize; 4]) -> isize {
     match syscall_id {
         SYSCALL_READ => sys_read(args[0], args[1] as *const u8, args[2]),
         SYSCALL_WRITE => sys_write(args[0], args[1] as *const u8, args[2]),
@@ -43,11 +51,17 @@ pub fn syscall(syscall_id: usize, args: [usize; 3]) -> isize {
         SYSCALL_EXEC => sys_exec(args[0] as *const u8),
         SYSCALL_WAITPID => sys_waitpid(args[0] as isize, args[1] as *mut i32),
         SYSCALL_GET_TIME => sys_get_time(args[0] as *mut TimeVal, args[1]),
-        SYSCALL_MMAP => sys_mmap(args[0], args[1], args[2]),
-        SYSCALL_MUNMAP => sys_munmap(args[0], args[1]),
+        SYSCALL_MMAP => sys_mmap(args[0], args[1], args[2], 0),
+        SYSCALL_MMAP_FLAGS => sys_mmap(args[0], args[1], args[2], args[3]),
+        SYSCALL_MUNMAP => sys_munmap(args[0], args[1]).min(0),
+        SYSCALL_MUNMAP_COUNT => sys_munmap(args[0], args[1]),
+        SYSCALL_MADVISE => sys_madvise(args[0], args[1], args[2]),
         SYSCALL_SET_PRIORITY => sys_set_priority(args[0] as isize),
         SYSCALL_TASK_INFO => sys_task_info(args[0] as *mut TaskInfo),
-        SYSCALL_SPAWN => sys_spawn(args[0] as *const u8),
+        SYSCALL_MEMORY_MAP => sys_memory_map(args[0], args[1]),
+        SYSCALL_MM_SELFTEST => sys_mm_selftest(),
+        SYSCALL_SPAWN => sys_spawn(args[0] as *const u8, args[1]),
+        SYSCALL_SETRLIMIT => sys_setrlimit(args[0], args[1]),
         _ => panic!("Unsupported syscall_id: {}", syscall_id),
     }
 }
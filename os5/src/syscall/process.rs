@@ -1,14 +1,18 @@
 //! Process management syscalls
 
-use crate::config::MAX_SYSCALL_NUM;
+use crate::config::{MAX_SYSCALL_NUM, PAGE_SIZE, USER_STACK_SIZE};
 use crate::loader::get_app_data_by_name;
-use crate::mm::{get_slice_buffer, translated_refmut, translated_str, munmap, mmap};
+use crate::mm::{
+    madvise, memory_map, mm_selftest, mmap, munmap, translated_refmut, translated_str,
+};
 use crate::task::{
     add_task, current_task, current_user_token, exit_current_and_run_next, get_cur_task_info,
     suspend_current_and_run_next, TaskStatus,
 };
 use crate::timer::get_time_us;
-use alloc::sync::Arc;
+
+/// resident set size limit, in bytes
+const RLIMIT_RSS: usize = 5;
 
 #[repr(C)]
 #[derive(Debug)]
@@ -24,6 +28,19 @@ pub struct TaskInfo {
     pub time: usize,
 }
 
+/// Copy `value` to `ptr` in the current user space, it may cross pages.
+fn copy_to_current_user<T>(ptr: usize, value: &T) -> isize {
+    let bytes = unsafe {
+        core::slice::from_raw_parts(value as *const T as *const u8, core::mem::size_of::<T>())
+    };
+    let task = current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    match inner.memory_set.copy_to_user(ptr, bytes) {
+        Ok(()) => 0,
+        Err(err) => err,
+    }
+}
+
 pub fn sys_exit(exit_code: i32) -> ! {
     debug!("[kernel] Application exited with code {}", exit_code);
     exit_current_and_run_next(exit_code);
@@ -61,8 +78,10 @@ pub fn sys_exec(path: *const u8) -> isize {
     let path = translated_str(token, path);
     if let Some(data) = get_app_data_by_name(path.as_str()) {
         let task = current_task().unwrap();
-        task.exec(data);
-        0
+        match task.exec(data) {
+            Ok(()) => 0,
+            Err(err) => err,
+        }
     } else {
         -1
     }
@@ -72,40 +91,13 @@ pub fn sys_exec(path: *const u8) -> isize {
 /// Else if there is a child process but it is still running, return -2.
 pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
     let task = current_task().unwrap();
-    // find a child process
-    // info!("waitpid");
-
-    // ---- access current TCB exclusively
-    let mut inner = task.inner_exclusive_access();
-    // inner.children.iter().for_each(|p| info!("{:?}",p.getpid()));
-
-    if !inner
-        .children
-        .iter()
-        .any(|p| pid == -1 || pid as usize == p.getpid())
-    {
-        return -1;
-        // ---- release current PCB
-    }
-    let pair = inner.children.iter().enumerate().find(|(_, p)| {
-        // ++++ temporarily access child PCB lock exclusively
-        p.inner_exclusive_access().is_zombie() && (pid == -1 || pid as usize == p.getpid())
-        // ++++ release child PCB
-    });
-    if let Some((idx, _)) = pair {
-        let child = inner.children.remove(idx);
-        // confirm that child will be deallocated after removing from children list
-        assert_eq!(Arc::strong_count(&child), 1);
-        let found_pid = child.getpid();
-        // ++++ temporarily access child TCB exclusively
-        let exit_code = child.inner_exclusive_access().exit_code;
-        // ++++ release child PCB
-        *translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
-        found_pid as isize
-    } else {
-        -2
+    match task.waitpid(pid) {
+        Ok((found_pid, exit_code)) => {
+            *translated_refmut(current_user_token(), exit_code_ptr) = exit_code;
+            found_pid as isize
+        }
+        Err(err) => err,
     }
-    // ---- release current PCB lock automatically
 }
 
 // YOUR JOB: 引入虚地址后重写 sys_get_time
@@ -114,22 +106,14 @@ pub fn sys_get_time(_ts: *mut TimeVal, _tz: usize) -> isize {
     let (sec, usec) = (_us / 1_000_000, _us % 1_000_000);
     // info!("[get time], sec{}", _us / 1_000_000);
     let ts = TimeVal { sec, usec };
-    if let Some(buffer) = get_slice_buffer::<TimeVal>(_ts as usize) {
-        *buffer = ts;
-        0
-    } else {
-        -1
-    }
+    copy_to_current_user(_ts as usize, &ts)
 }
 
 // YOUR JOB: 引入虚地址后重写 sys_task_info
 pub fn sys_task_info(ti: *mut TaskInfo) -> isize {
     let task_info = get_cur_task_info();
     if let Some(info) = task_info {
-        if let Some(buffer) = get_slice_buffer::<TaskInfo>(ti as usize) {
-            *buffer = info;
-            return 0;
-        }
+        return copy_to_current_user(ti as usize, &info);
     }
     -1
 }
@@ -144,23 +128,63 @@ pub fn sys_set_priority(_prio: isize) -> isize {
 }
 
 // YOUR JOB: 扩展内核以实现 sys_mmap 和 sys_munmap
-pub fn sys_mmap(_start: usize, _len: usize, _prot: usize) -> isize {
-    mmap(_start, _len, _prot)
+pub fn sys_mmap(_start: usize, _len: usize, _prot: usize, _flags: usize) -> isize {
+    mmap(_start, _len, _prot, _flags)
 }
 
+/// Returns the number of pages unmapped, or -1.
 pub fn sys_munmap(_start: usize, _len: usize) -> isize {
     munmap(_start, _len)
 }
 
+/// Fill `buf` with up to `len` records of the current address space,
+/// returns the number of records written.
+pub fn sys_memory_map(buf: usize, len: usize) -> isize {
+    memory_map(buf, len)
+}
+
+/// Stress `mmap` and `munmap` on a scratch space, returns 0 or the negative
+/// code of the first broken invariant.
+pub fn sys_mm_selftest() -> isize {
+    mm_selftest()
+}
+
+/// Only `MADV_DONTNEED` is supported.
+pub fn sys_madvise(start: usize, len: usize, advice: usize) -> isize {
+    madvise(start, len, advice)
+}
+
+/// Only `RLIMIT_RSS` is supported, the limit is rounded down to whole pages.
+pub fn sys_setrlimit(resource: usize, limit: usize) -> isize {
+    if resource != RLIMIT_RSS {
+        return -1;
+    }
+    if let Some(cur_task) = current_task() {
+        cur_task
+            .inner_exclusive_access()
+            .memory_set
+            .set_frame_limit(limit / PAGE_SIZE);
+        0
+    } else {
+        -1
+    }
+}
+
 //
 // YOUR JOB: 实现 sys_spawn 系统调用
 // ALERT: 注意在实现 SPAWN 时不需要复制父进程地址空间，SPAWN != FORK + EXEC
-pub fn sys_spawn(path: *const u8) -> isize {
+/// A `stack_size` of 0 gives the child the default user stack.
+pub fn sys_spawn(path: *const u8, stack_size: usize) -> isize {
+    let stack_size = if stack_size == 0 {
+        USER_STACK_SIZE
+    } else {
+        stack_size
+    };
     if let Some(cur_task) = current_task() {
         let token = current_user_token();
         let path = translated_str(token, path);
         if let Some(data) = get_app_data_by_name(path.as_str()) {
-            if let Ok(child) = cur_task.spawn(data) {
+            if let Ok(child) = cur_task.spawn(data, stack_size) {
                 let ctx = child.inner_exclusive_access().get_trap_cx();
                 // info!("context: {:?}",ctx.x);
                 ctx.x[10] = 0;
@@ -22,11 +22,13 @@ use alloc::sync::Arc;
 use lazy_static::*;
 use manager::fetch_task;
 use switch::__switch;
-pub use task::{TaskControlBlock, TaskStatus};
+pub use task::{wait_exit_test, TaskControlBlock, TaskStatus};
 pub use processor::get_cur_task_info;
 pub use context::TaskContext;
 pub use manager::add_task;
-pub use pid::{pid_alloc, KernelStack, PidHandle};
+pub use pid::{
+    kernel_stack_guard_owner, kernel_stack_guard_test, pid_alloc, KernelStack, PidHandle,
+};
 pub use processor::{
     current_task, current_trap_cx, current_user_token, run_tasks, schedule, take_current_task,
 };
@@ -58,10 +60,8 @@ pub fn exit_current_and_run_next(exit_code: i32) {
     let task = take_current_task().unwrap();
     // **** access current TCB exclusively
     let mut inner = task.inner_exclusive_access();
-    // Change status to Zombie
-    inner.task_status = TaskStatus::Zombie;
-    // Record exit code
-    inner.exit_code = exit_code;
+    // Change status to Zombie, record exit code and deallocate user space
+    inner.exit(exit_code);
     // do not move to its parent but under initproc
 
     // ++++++ access initproc TCB exclusively
@@ -75,8 +75,6 @@ pub fn exit_current_and_run_next(exit_code: i32) {
     // ++++++ release parent PCB
 
     inner.children.clear();
-    // deallocate user space
-    inner.memory_set.recycle_data_pages();
     drop(inner);
     // **** release current PCB
     // drop task manually to maintain rc correctly
@@ -4,8 +4,9 @@
 //! is determined according to the PID.
 
 use crate::config::{KERNEL_STACK_SIZE, PAGE_SIZE, TRAMPOLINE};
-use crate::mm::{MapPermission, VirtAddr, KERNEL_SPACE};
+use crate::mm::{MapPermission, VirtAddr, VirtPageNum, KERNEL_SPACE};
 use crate::sync::UPSafeCell;
+use crate::trap::probe_write;
 use alloc::vec::Vec;
 use lazy_static::*;
 
@@ -64,12 +65,33 @@ pub fn pid_alloc() -> PidHandle {
 }
 
 /// Return (bottom, top) of a kernel stack in kernel space.
+///
+/// The page right below `bottom` is never mapped, it is the guard page that
+/// turns an overflow of the kernel stack into a page fault.
 pub fn kernel_stack_position(app_id: usize) -> (usize, usize) {
     let top = TRAMPOLINE - app_id * (KERNEL_STACK_SIZE + PAGE_SIZE);
     let bottom = top - KERNEL_STACK_SIZE;
     (bottom, top)
 }
 
+/// Return the pid whose kernel stack guard page contains `va`, if any.
+pub fn kernel_stack_guard_owner(va: usize) -> Option<usize> {
+    if va >= TRAMPOLINE {
+        return None;
+    }
+    let slot = KERNEL_STACK_SIZE + PAGE_SIZE;
+    let pid = (TRAMPOLINE - 1 - va) / slot;
+    if TRAMPOLINE - pid * slot < slot {
+        return None;
+    }
+    let (bottom, _) = kernel_stack_position(pid);
+    if va >= bottom - PAGE_SIZE && va < bottom {
+        Some(pid)
+    } else {
+        None
+    }
+}
+
 /// KernelStack corresponding to PID
 pub struct KernelStack {
     pid: usize,
@@ -114,3 +136,27 @@ impl Drop for KernelStack {
             .remove_area_with_start_vpn(kernel_stack_bottom_va.into());
     }
 }
+
+#[allow(unused)]
+/// Overflow a fresh kernel stack by one byte and check that it faults in the
+/// guard page instead of writing into the stack below it.
+pub fn kernel_stack_guard_test() {
+    let pid = pid_alloc();
+    let kernel_stack = KernelStack::new(&pid);
+    let (bottom, top) = kernel_stack_position(pid.0);
+    assert_eq!(kernel_stack.get_top(), top);
+    let guard = bottom - PAGE_SIZE;
+    let guard_vpn: VirtPageNum = VirtAddr::from(guard).floor();
+    assert!(KERNEL_SPACE
+        .exclusive_access()
+        .translate(guard_vpn)
+        .map_or(true, |pte| !pte.is_valid()));
+    assert_eq!(probe_write(bottom), Ok(()));
+    assert_eq!(probe_write(bottom - 1), Err(bottom - 1));
+    assert_eq!(probe_write(guard), Err(guard));
+    assert_eq!(kernel_stack_guard_owner(bottom - 1), Some(pid.0));
+    assert_eq!(kernel_stack_guard_owner(guard), Some(pid.0));
+    assert_eq!(kernel_stack_guard_owner(bottom), None);
+    assert_eq!(kernel_stack_guard_owner(guard - 1), None);
+    info!("kernel_stack_guard_test passed!");
+}
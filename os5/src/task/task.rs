@@ -2,8 +2,9 @@
 
 use super::TaskContext;
 use super::{pid_alloc, KernelStack, PidHandle};
-use crate::config::{BIG_STRIDE, TRAP_CONTEXT, MAX_SYSCALL_NUM};
-use crate::mm::{MemorySet, PhysPageNum, VirtAddr, KERNEL_SPACE};
+use crate::config::{BIG_STRIDE, MAX_SYSCALL_NUM, PAGE_SIZE, TRAP_CONTEXT, USER_STACK_SIZE};
+use crate::loader::get_app_data_by_name;
+use crate::mm::{frame_free_count, MemorySet, PhysPageNum, VirtAddr, KERNEL_SPACE};
 use crate::sync::UPSafeCell;
 use crate::syscall::TaskInfo;
 use crate::timer::get_time_us;
@@ -77,6 +78,13 @@ impl TaskControlBlockInner {
     pub fn is_zombie(&self) -> bool {
         self.get_status() == TaskStatus::Zombie
     }
+    /// Become a zombie which keeps nothing but `exit_code`,
+    /// the address space is recycled at once.
+    pub fn exit(&mut self, exit_code: i32) {
+        self.task_status = TaskStatus::Zombie;
+        self.exit_code = exit_code;
+        self.memory_set.recycle_data_pages();
+    }
 }
 
 impl TaskControlBlock {
@@ -90,7 +98,7 @@ impl TaskControlBlock {
     /// At present, it is only used for the creation of initproc
     pub fn new(elf_data: &[u8]) -> Self {
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data).unwrap();
         let trap_cx_ppn = memory_set
             .translate(VirtAddr::from(TRAP_CONTEXT).into())
             .unwrap()
@@ -133,9 +141,12 @@ impl TaskControlBlock {
         task_control_block
     }
     /// Load a new elf to replace the original application address space and start execution
-    pub fn exec(&self, elf_data: &[u8]) {
+    ///
+    /// The original address space is kept if the elf is rejected.
+    pub fn exec(&self, elf_data: &[u8]) -> Result<(), isize> {
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let (mut memory_set, user_sp, entry_point) =
+            MemorySet::from_elf(elf_data).map_err(|_| -1)?;
         let trap_cx_ppn = memory_set
             .translate(VirtAddr::from(TRAP_CONTEXT).into())
             .unwrap()
@@ -143,6 +154,10 @@ impl TaskControlBlock {
 
         // **** access inner exclusively
         let mut inner = self.inner_exclusive_access();
+        // frame limit survives exec
+        memory_set.set_frame_limit(inner.memory_set.frame_limit());
+        // dirty shared pages of the old space are written back before it goes
+        inner.memory_set.recycle_data_pages();
         // substitute memory_set
         inner.memory_set = memory_set;
         // update trap_cx ppn
@@ -157,6 +172,7 @@ impl TaskControlBlock {
             trap_handler as usize,
         );
         // **** release inner automatically
+        Ok(())
     }
     /// Fork from parent to child
     pub fn fork(self: &Arc<TaskControlBlock>) -> Arc<TaskControlBlock> {
@@ -214,7 +230,56 @@ impl TaskControlBlock {
     pub fn getpid(&self) -> usize {
         self.pid.0
     }
-    pub fn spawn(self: &Arc<TaskControlBlock>, elf_data: &[u8]) -> Result<Arc<TaskControlBlock>, isize> {
+    /// Reap a zombie child, any child if `pid` is -1, returning its pid and exit code.
+    ///
+    /// Fails with -1 if there is no such child and -2 if it is still running.
+    pub fn waitpid(&self, pid: isize) -> Result<(usize, i32), isize> {
+        // ---- access current TCB exclusively
+        let mut inner = self.inner_exclusive_access();
+        if !inner
+            .children
+            .iter()
+            .any(|p| pid == -1 || pid as usize == p.getpid())
+        {
+            return Err(-1);
+            // ---- release current PCB
+        }
+        let pair = inner.children.iter().enumerate().find(|(_, p)| {
+            // ++++ temporarily access child PCB lock exclusively
+            p.inner_exclusive_access().is_zombie() && (pid == -1 || pid as usize == p.getpid())
+            // ++++ release child PCB
+        });
+        if let Some((idx, _)) = pair {
+            let child = inner.children.remove(idx);
+            // confirm that child will be deallocated after removing from children list
+            assert_eq!(Arc::strong_count(&child), 1);
+            let found_pid = child.getpid();
+            // ++++ temporarily access child TCB exclusively
+            let exit_code = child.inner_exclusive_access().exit_code;
+            // ++++ release child PCB
+            Ok((found_pid, exit_code))
+        } else {
+            Err(-2)
+        }
+        // ---- release current PCB lock automatically
+    }
+    /// Create a child running `elf_data` with a user stack of `stack_size` bytes.
+    pub fn spawn(
+        self: &Arc<TaskControlBlock>,
+        elf_data: &[u8],
+        stack_size: usize,
+    ) -> Result<Arc<TaskControlBlock>, isize> {
+        // child inherits the frame limit, so the stack must fit in it
+        let frame_limit = self.inner_exclusive_access().memory_set.frame_limit();
+        if stack_size / PAGE_SIZE >= frame_limit {
+            return Err(-1);
+        }
+        let (mut memory_set, user_sp, entry_point) =
+            MemorySet::from_elf_with_stack(elf_data, stack_size).map_err(|_| -1)?;
+        if memory_set.frame_count() > frame_limit {
+            return Err(-1);
+        }
+        memory_set.set_frame_limit(frame_limit);
         // ---- access parent PCB exclusively
         let mut parent_inner = self.inner_exclusive_access();
         // alloc a pid and a kernel stack in kernel space
@@ -222,7 +287,6 @@ impl TaskControlBlock {
         let kernel_stack = KernelStack::new(&pid);
         let kernel_stack_top = kernel_stack.get_top();
 
-        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
         if let Some(pte) = memory_set.translate(VirtAddr::from(TRAP_CONTEXT).into()) {
             let trap_cx_ppn = pte.ppn();
             let cx = trap_cx_ppn.get_mut::<TrapContext>();
@@ -292,3 +356,30 @@ pub enum TaskStatus {
     Running,
     Zombie,
 }
+
+#[allow(unused)]
+pub fn wait_exit_test() {
+    let elf_data = get_app_data_by_name("ch5b_initproc").unwrap();
+    let parent = Arc::new(TaskControlBlock::new(elf_data));
+    let free_before = frame_free_count();
+    let child = parent.spawn(elf_data, USER_STACK_SIZE).unwrap();
+    let pid = child.getpid();
+    assert_eq!(parent.waitpid(pid as isize), Err(-2));
+    assert_eq!(parent.waitpid(pid as isize + 1), Err(-1));
+    child.inner_exclusive_access().exit(7);
+    // the zombie has already given its user space back
+    assert_eq!(child.inner_exclusive_access().memory_set.frame_count(), 0);
+    drop(child);
+    assert_eq!(parent.waitpid(-1), Ok((pid, 7)));
+    // and its kernel stack along with the TCB once waited for
+    assert_eq!(frame_free_count(), free_before);
+    assert_eq!(parent.waitpid(pid as isize), Err(-1));
+    // a stack larger than the inherited frame limit is refused
+    parent.inner_exclusive_access().memory_set.set_frame_limit(8);
+    assert!(parent.spawn(elf_data, 16 * PAGE_SIZE).is_err());
+    assert!(parent.spawn(elf_data, usize::MAX).is_err());
+    // fits on its own, but not together with the elf pages
+    assert!(parent.spawn(elf_data, 7 * PAGE_SIZE).is_err());
+    assert_eq!(frame_free_count(), free_before);
+    info!("wait_exit_test passed!");
+}
@@ -11,22 +11,41 @@
 //! It then calls different functionality based on what exactly the exception
 //! was. For example, timer interrupts trigger task preemption, and syscalls go
 //! to [`syscall()`].
+//!
+//! Traps from kernel enter `__trap_from_kernel` in `kernel_trap.S`, which
+//! switches to a stack of its own before calling [`trap_from_kernel()`], so
+//! that an overflow into the guard page below a kernel stack still gets
+//! reported.
 
 mod context;
 
 use crate::config::{TRAMPOLINE, TRAP_CONTEXT};
+use crate::mm::{FaultCause, FaultResult};
 use crate::syscall::syscall;
 use crate::task::{
-    current_trap_cx, current_user_token, exit_current_and_run_next, suspend_current_and_run_next,
+    current_task, current_trap_cx, current_user_token, exit_current_and_run_next,
+    kernel_stack_guard_owner, suspend_current_and_run_next,
 };
 use crate::timer::set_next_trigger;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use riscv::register::{
     mtvec::TrapMode,
     scause::{self, Exception, Interrupt, Trap},
-    sie, stval, stvec,
+    sepc, sie, stval, stvec,
 };
 
 core::arch::global_asm!(include_str!("trap.S"));
+core::arch::global_asm!(include_str!("kernel_trap.S"));
+
+extern "C" {
+    fn __trap_from_kernel();
+    fn __probe_write(addr: usize) -> usize;
+    fn __probe_insn();
+    fn __probe_fixup();
+}
+
+/// Faulting address of the last [`probe_write`] that faulted.
+static PROBE_FAULT: AtomicUsize = AtomicUsize::new(0);
 
 pub fn init() {
     set_kernel_trap_entry();
@@ -34,7 +53,7 @@ pub fn init() {
 
 fn set_kernel_trap_entry() {
     unsafe {
-        stvec::write(trap_from_kernel as usize, TrapMode::Direct);
+        stvec::write(__trap_from_kernel as usize, TrapMode::Direct);
     }
 }
 
@@ -61,17 +80,40 @@ pub fn trap_handler() -> ! {
             let mut cx = current_trap_cx();
             cx.sepc += 4;
             // get system call return value
-            let result = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12]]);
+            let result = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12], cx.x[13]]);
             // cx is changed during sys_exec, so we have to call it again
             cx = current_trap_cx();
             cx.x[10] = result as usize;
         }
-        Trap::Exception(Exception::StoreFault)
-        | Trap::Exception(Exception::StorePageFault)
-        | Trap::Exception(Exception::InstructionFault)
+        Trap::Exception(Exception::StorePageFault)
         | Trap::Exception(Exception::InstructionPageFault)
-        | Trap::Exception(Exception::LoadFault)
         | Trap::Exception(Exception::LoadPageFault) => {
+            let cause = match scause.cause() {
+                Trap::Exception(Exception::StorePageFault) => FaultCause::Store,
+                Trap::Exception(Exception::InstructionPageFault) => FaultCause::Instruction,
+                _ => FaultCause::Load,
+            };
+            let result = current_task()
+                .unwrap()
+                .inner_exclusive_access()
+                .memory_set
+                .handle_page_fault(stval.into(), cause);
+            // a resolved fault returns to the same instruction
+            if result != FaultResult::Resolved {
+                println!(
+                    "[kernel] {:?} in application, bad addr = {:#x}, bad instruction = {:#x}, {:?}, core dumped.",
+                    scause.cause(),
+                    stval,
+                    current_trap_cx().sepc,
+                    result,
+                );
+                // page fault exit code
+                exit_current_and_run_next(-2);
+            }
+        }
+        Trap::Exception(Exception::StoreFault)
+        | Trap::Exception(Exception::InstructionFault)
+        | Trap::Exception(Exception::LoadFault) => {
             println!(
                 "[kernel] {:?} in application, bad addr = {:#x}, bad instruction = {:#x}, core dumped.",
                 scause.cause(),
@@ -123,9 +165,45 @@ pub fn trap_return() -> ! {
     }
 }
 
+/// Traps from kernel are fatal, except for page faults of [`probe_write`].
+/// A fault in the guard page below a kernel stack is reported as an overflow
+/// of that stack.
 #[no_mangle]
-pub fn trap_from_kernel() -> ! {
-    panic!("a trap {:?} from kernel!", scause::read().cause());
+pub extern "C" fn trap_from_kernel() {
+    let scause = scause::read();
+    let stval = stval::read();
+    match scause.cause() {
+        Trap::Exception(Exception::StorePageFault) if sepc::read() == __probe_insn as usize => {
+            PROBE_FAULT.store(stval, Ordering::Relaxed);
+            sepc::write(__probe_fixup as usize);
+        }
+        Trap::Exception(Exception::StorePageFault) | Trap::Exception(Exception::LoadPageFault) => {
+            if let Some(pid) = kernel_stack_guard_owner(stval) {
+                panic!(
+                    "kernel stack overflow of pid {}, bad addr = {:#x}, bad instruction = {:#x}",
+                    pid,
+                    stval,
+                    sepc::read()
+                );
+            }
+            panic!(
+                "a trap {:?} from kernel, bad addr = {:#x}!",
+                scause.cause(),
+                stval
+            );
+        }
+        _ => panic!("a trap {:?} from kernel!", scause.cause()),
+    }
+}
+
+/// Store a zero byte to `addr` from kernel, a page fault gives back the
+/// faulting address instead of a panic.
+pub fn probe_write(addr: usize) -> Result<(), usize> {
+    if unsafe { __probe_write(addr) } == 0 {
+        Ok(())
+    } else {
+        Err(PROBE_FAULT.load(Ordering::Relaxed))
+    }
 }
 
 pub use context::TrapContext;
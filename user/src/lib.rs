@@ -284,16 +284,75 @@ pub fn sleep(period_ms: usize) {
         sys_yield();
     }
 }
+/// Writes are seen by children forked after the map, and by the parent.
+pub const MAP_SHARED: usize = 0x01;
+pub const MAP_PRIVATE: usize = 0x02;
+pub const MAP_FIXED: usize = 0x10;
+pub const MAP_GROWSDOWN: usize = 0x100;
+
 pub fn mmap(start: usize, len: usize, prot: usize) -> isize {
     sys_mmap(start, len, prot)
 }
 
+/// Without `MAP_FIXED`, `start` is a hint and the mapped address is returned.
+pub fn mmap_with_flags(start: usize, len: usize, prot: usize, flags: usize) -> isize {
+    sys_mmap_with_flags(start, len, prot, flags)
+}
+
 pub fn munmap(start: usize, len: usize) -> isize {
     sys_munmap(start, len)
 }
 
+/// Same as `munmap`, but returns the number of pages unmapped.
+pub fn munmap_count(start: usize, len: usize) -> isize {
+    sys_munmap_count(start, len)
+}
+
+pub const MADV_DONTNEED: usize = 4;
+
+pub fn madvise(start: usize, len: usize, advice: usize) -> isize {
+    sys_madvise(start, len, advice)
+}
+
+/// `mmap` past the limit set by `setrlimit` returns `-ENOMEM`.
+pub const ENOMEM: isize = 12;
+
+pub fn setrlimit(resource: usize, limit: usize) -> isize {
+    sys_setrlimit(resource, limit)
+}
+
+/// One area of the address space, `perm` holds the pte `R W X U` bits and
+/// `map_type` is 0 for identical and 1 for framed mappings. `name` is a NUL
+/// padded tag such as `elf`, `ustack`, `trapctx` or `mmap`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MapRecord {
+    pub start_va: usize,
+    pub end_va: usize,
+    pub perm: usize,
+    pub map_type: usize,
+    pub name: [u8; 8],
+}
+
+/// Returns the number of records filled.
+pub fn memory_map(records: &mut [MapRecord]) -> isize {
+    sys_memory_map(records)
+}
+
+/// Returns 0, or the negative code of the first invariant the kernel found
+/// broken while stressing `mmap` and `munmap`.
+pub fn mm_selftest() -> isize {
+    sys_mm_selftest()
+}
+
 pub fn spawn(path: &str) -> isize {
-    sys_spawn(path)
+    sys_spawn(path, 0)
+}
+
+/// Like `spawn`, but the child gets a user stack of `stack_size` bytes
+/// rounded up to pages instead of the default one.
+pub fn spawn_with_stack(path: &str, stack_size: usize) -> isize {
+    sys_spawn(path, stack_size)
 }
 
 pub fn dup(fd: usize) -> isize {
@@ -1,4 +1,4 @@
-use crate::TaskInfo;
+use crate::{MapRecord, TaskInfo};
 
 use super::{Stat, TimeVal};
 
@@ -13,6 +13,7 @@ pub const SYSCALL_EXIT: usize = 93;
 pub const SYSCALL_SLEEP: usize = 101;
 pub const SYSCALL_YIELD: usize = 124;
 pub const SYSCALL_GETTIMEOFDAY: usize = 169;
+pub const SYSCALL_SETRLIMIT: usize = 164;
 pub const SYSCALL_GETPID: usize = 172;
 pub const SYSCALL_GETTID: usize = 178;
 pub const SYSCALL_FORK: usize = 220;
@@ -21,12 +22,17 @@ pub const SYSCALL_WAITPID: usize = 260;
 pub const SYSCALL_SET_PRIORITY: usize = 140;
 pub const SYSCALL_MUNMAP: usize = 215;
 pub const SYSCALL_MMAP: usize = 222;
+pub const SYSCALL_MADVISE: usize = 233;
 pub const SYSCALL_SPAWN: usize = 400;
 pub const SYSCALL_MAIL_READ: usize = 401;
 pub const SYSCALL_MAIL_WRITE: usize = 402;
 pub const SYSCALL_DUP: usize = 24;
 pub const SYSCALL_PIPE: usize = 59;
 pub const SYSCALL_TASK_INFO: usize = 410;
+pub const SYSCALL_MEMORY_MAP: usize = 411;
+pub const SYSCALL_MM_SELFTEST: usize = 412;
+pub const SYSCALL_MMAP_FLAGS: usize = 413;
+pub const SYSCALL_MUNMAP_COUNT: usize = 414;
 pub const SYSCALL_THREAD_CREATE: usize = 460;
 pub const SYSCALL_WAITTID: usize = 462;
 pub const SYSCALL_MUTEX_CREATE: usize = 463;
@@ -185,12 +191,28 @@ pub fn sys_mmap(start: usize, len: usize, prot: usize) -> isize {
     syscall(SYSCALL_MMAP, [start, len, prot])
 }
 
+pub fn sys_mmap_with_flags(start: usize, len: usize, prot: usize, flags: usize) -> isize {
+    syscall6(SYSCALL_MMAP_FLAGS, [start, len, prot, flags, 0, 0])
+}
+
 pub fn sys_munmap(start: usize, len: usize) -> isize {
     syscall(SYSCALL_MUNMAP, [start, len, 0])
 }
 
-pub fn sys_spawn(path: &str) -> isize {
-    syscall(SYSCALL_SPAWN, [path.as_ptr() as usize, 0, 0])
+pub fn sys_munmap_count(start: usize, len: usize) -> isize {
+    syscall(SYSCALL_MUNMAP_COUNT, [start, len, 0])
+}
+
+pub fn sys_madvise(start: usize, len: usize, advice: usize) -> isize {
+    syscall(SYSCALL_MADVISE, [start, len, advice])
+}
+
+pub fn sys_setrlimit(resource: usize, limit: usize) -> isize {
+    syscall(SYSCALL_SETRLIMIT, [resource, limit, 0])
+}
+
+pub fn sys_spawn(path: &str, stack_size: usize) -> isize {
+    syscall(SYSCALL_SPAWN, [path.as_ptr() as usize, stack_size, 0])
 }
 
 pub fn sys_dup(fd: usize) -> isize {
@@ -205,6 +227,17 @@ pub fn sys_task_info(info: &TaskInfo) -> isize {
     syscall(SYSCALL_TASK_INFO, [info as *const _ as usize, 0, 0])
 }
 
+pub fn sys_memory_map(records: &mut [MapRecord]) -> isize {
+    syscall(
+        SYSCALL_MEMORY_MAP,
+        [records.as_mut_ptr() as usize, records.len(), 0],
+    )
+}
+
+pub fn sys_mm_selftest() -> isize {
+    syscall(SYSCALL_MM_SELFTEST, [0, 0, 0])
+}
+
 pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
     syscall(SYSCALL_THREAD_CREATE, [entry, arg, 0])
 }